@@ -0,0 +1,58 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use transaction_engine::generator::{generate, GeneratorConfig};
+use transaction_engine::output::output_accounts;
+use transaction_engine::parser::parse_reader;
+use transaction_engine::scale::Scale;
+use transaction_engine::transaction_engine::TransactionEngine;
+
+const ROWS: u64 = 100_000;
+
+fn input() -> Vec<u8> {
+    let config = GeneratorConfig{rows: ROWS, clients: 1000, ..GeneratorConfig::default()};
+    let mut input = Vec::new();
+    generate(&config, &mut input).unwrap();
+    input
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let input = input();
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(ROWS));
+    group.bench_function("parse_reader", |b| b.iter(|| parse_reader(input.as_slice()).unwrap()));
+    group.finish();
+}
+
+fn bench_process(c: &mut Criterion) {
+    let transactions = parse_reader(input().as_slice()).unwrap();
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Elements(transactions.len() as u64));
+    group.bench_function("process_transaction", |b| b.iter_batched(
+        || transactions.clone(),
+        |transactions| {
+            let mut te = TransactionEngine::new();
+            for transaction in transactions {
+                te.process_transaction(transaction);
+            }
+            te
+        },
+        BatchSize::LargeInput));
+    group.finish();
+}
+
+fn bench_output(c: &mut Criterion) {
+    let mut te = TransactionEngine::new();
+    for transaction in parse_reader(input().as_slice()).unwrap() {
+        te.process_transaction(transaction);
+    }
+    let mut group = c.benchmark_group("output");
+    group.throughput(Throughput::Elements(te.get_accounts().map(Result::unwrap).count() as u64));
+    group.bench_function("output_accounts", |b| b.iter(|| {
+        let mut output = Vec::new();
+        output_accounts(&mut output, Scale::default(), te.get_accounts().map(Result::unwrap)).unwrap();
+        output
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_process, bench_output);
+criterion_main!(benches);
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use transaction_engine::parser::parse_reader;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_reader(data);
+});
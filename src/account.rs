@@ -1,83 +1,191 @@
-use crate::common_types::Amount;
+use crate::common_types::{Amount, RejectReason, TransactionID};
+use crate::events::Event;
+use crate::ledger::Balance;
 use log::{warn, error};
 
+// an account is only ever changed by applying its events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Account {
-    amount: Amount,
-    amount_held: Amount,
+    balance: Balance,
     is_locked: bool
 }
 
+impl Default for Account {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Account {
-    pub fn new(amount: Amount) -> Self {
+    pub fn new() -> Self {
         Self {
-            amount,
-            amount_held: 0,
+            balance: Balance::default(),
             is_locked: false
         }
     }
 
-    pub fn deposit(&mut self, amount: Amount) -> bool {
+    // rebuilds an account from a stored snapshot
+    pub fn restore(balance: Balance, is_locked: bool) -> Self {
+        Self {
+            balance,
+            is_locked
+        }
+    }
+
+    // rebuilds an account from all its events
+    pub fn replay<I: IntoIterator<Item=Event>>(events: I) -> Self {
+        let mut account = Self::new();
+        events.into_iter().for_each(|event| account.apply(&event));
+        account
+    }
+
+    pub fn apply(&mut self, event: &Event) {
+        if let Some(posting) = event.posting() {
+            self.balance.apply(&posting);
+        }
+        if event.locks() {
+            self.is_locked = true;
+        }
+    }
+
+    fn record(&mut self, event: Event) -> Result<Event, RejectReason> {
+        if event.posting().is_some_and(|posting| self.balance.after(&posting).is_none()) {
+            warn!("Attempt to trigger an account action past the largest amount");
+            return Err(RejectReason::Overflow);
+        }
+        self.apply(&event);
+        Ok(event)
+    }
+
+    pub fn deposit(&mut self, tx: TransactionID, amount: Amount) -> Result<Event, RejectReason> {
         if !self.is_locked {
-            self.amount += amount;
-            true
+            self.record(Event::Deposited(tx, amount))
         } else {
             warn!("Attempt to trigger a deposit account action on a locked account");
-            false
+            Err(RejectReason::LockedAccount)
         }
     }
 
-    pub fn withdraw(&mut self, amount: Amount) -> bool {
+    pub fn withdraw(&mut self, tx: TransactionID, amount: Amount) -> Result<Event, RejectReason> {
         if amount <= self.available() && !self.is_locked() {
-            self.amount -= amount;
-            true
+            self.record(Event::Withdrew(tx, amount))
         } else {
             warn!("Attempt to trigger a withdrawal account action not enough funds or locked account");
-            false
+            Err(if self.is_locked() { RejectReason::LockedAccount } else { RejectReason::InsufficientFunds })
         }
     }
 
-    pub fn dispute(&mut self, amount: Amount) -> bool {
+    pub fn dispute(&mut self, tx: TransactionID, amount: Amount) -> Result<Event, RejectReason> {
         if amount <= self.available() {
-            self.amount_held += amount;
-            true
+            self.record(Event::DisputeOpened(tx, amount))
         } else {
             error!("Attempt to trigger a dispute account action without enough funds");
-            false
+            Err(RejectReason::InsufficientFunds)
         }
     }
 
-    pub fn resolve(&mut self, amount: Amount) -> bool {
+    pub fn resolve(&mut self, tx: TransactionID, amount: Amount) -> Result<Event, RejectReason> {
         if amount <= self.held() {
-            self.amount_held -= amount;
-            true
+            self.record(Event::DisputeResolved(tx, amount))
         } else {
             error!("Attempt to trigger a resolve account action without enough held funds");
-            false
+            Err(RejectReason::InsufficientFunds)
         }
     }
 
-    pub fn chargeback(&mut self, amount: Amount) -> bool {
+    pub fn chargeback(&mut self, tx: TransactionID, amount: Amount) -> Result<Event, RejectReason> {
         if amount <= self.held() {
-            self.is_locked = true;
-            self.amount_held -= amount;
-            self.amount -= amount;
-            true
+            self.record(Event::ChargedBack(tx, amount))
         } else {
             error!("Attempt to trigger a chargeback account action without enough held funds");
-            false
+            Err(RejectReason::InsufficientFunds)
+        }
+    }
+
+    // a withdrawal dispute holds the withdrawn amount back from the settlement, the available funds are not touched
+    pub fn dispute_withdrawal(&mut self, tx: TransactionID, amount: Amount) -> Result<Event, RejectReason> {
+        self.record(Event::WithdrawalDisputeOpened(tx, amount))
+    }
+
+    pub fn resolve_withdrawal(&mut self, tx: TransactionID, amount: Amount) -> Result<Event, RejectReason> {
+        if amount <= self.held() {
+            self.record(Event::WithdrawalDisputeResolved(tx, amount))
+        } else {
+            error!("Attempt to trigger a resolve account action without enough held funds");
+            Err(RejectReason::InsufficientFunds)
+        }
+    }
+
+    pub fn chargeback_withdrawal(&mut self, tx: TransactionID, amount: Amount) -> Result<Event, RejectReason> {
+        if amount <= self.held() {
+            self.record(Event::WithdrawalChargedBack(tx, amount))
+        } else {
+            error!("Attempt to trigger a chargeback account action without enough held funds");
+            Err(RejectReason::InsufficientFunds)
+        }
+    }
+
+    pub fn charge_fee(&mut self, tx: TransactionID, amount: Amount) -> Result<Event, RejectReason> {
+        if amount <= self.available() {
+            self.record(Event::FeeCharged(tx, amount))
+        } else {
+            warn!("Attempt to charge a fee without enough funds");
+            Err(RejectReason::InsufficientFunds)
+        }
+    }
+
+    pub fn receive_interest(&mut self, amount: Amount) -> Result<Event, RejectReason> {
+        self.record(Event::InterestPaid(amount))
+    }
+
+    // takes a deposit back, as long as its funds are still available
+    pub fn reverse_deposit(&mut self, tx: TransactionID, amount: Amount) -> Result<Event, RejectReason> {
+        if self.is_locked() {
+            warn!("Attempt to trigger a reversal account action on a locked account");
+            Err(RejectReason::LockedAccount)
+        } else if amount <= self.available() {
+            self.record(Event::DepositReversed(tx, amount))
+        } else {
+            warn!("Attempt to trigger a reversal account action on funds that are no longer available");
+            Err(RejectReason::InsufficientFunds)
+        }
+    }
+
+    pub fn reverse_withdrawal(&mut self, tx: TransactionID, amount: Amount) -> Result<Event, RejectReason> {
+        if !self.is_locked() {
+            self.record(Event::WithdrawalReversed(tx, amount))
+        } else {
+            warn!("Attempt to trigger a reversal account action on a locked account");
+            Err(RejectReason::LockedAccount)
+        }
+    }
+
+    pub fn refund_fee(&mut self, tx: TransactionID, amount: Amount) -> Result<Event, RejectReason> {
+        if !self.is_locked() {
+            self.record(Event::FeeRefunded(tx, amount))
+        } else {
+            warn!("Attempt to refund a fee on a locked account");
+            Err(RejectReason::LockedAccount)
         }
     }
 
+    // locks the account without moving any funds, `tx` is the transaction that caused it
+    pub fn lock(&mut self, tx: TransactionID) -> Event {
+        let event = Event::Locked(tx);
+        self.apply(&event);
+        event
+    }
+
     pub fn available(&self) -> Amount {
-        self.amount - self.amount_held
+        self.balance.available
     }
 
     pub fn held(&self) -> Amount {
-        self.amount_held
+        self.balance.held
     }
 
     pub fn total(&self) -> Amount {
-        self.amount
+        self.balance.total()
     }
 
     pub fn is_locked(&self) -> bool {
@@ -86,157 +194,256 @@ impl Account {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use crate::account::Account;
+    use crate::common_types::{Amount, RejectReason, SignedAmount};
+    use crate::events::Event;
+
+    fn account(available: Amount, held: Amount) -> Account {
+        let mut account = Account::new();
+        account.deposit(1, available + held).unwrap();
+        account.dispute(1, held).unwrap();
+        account
+    }
 
     #[test]
     fn create_account() {
-        let account = Account::new(42);
-        assert_eq!(account.amount, 42);
-        assert_eq!(account.amount_held, 0);
+        let account = Account::new();
+        assert_eq!(account.total(), 0);
+        assert_eq!(account.held(), 0);
         assert_eq!(account.is_locked, false);
     }
 
+    #[test]
+    fn apply_events() {
+        let mut account = Account::new();
+        account.apply(&Event::Deposited(1, 42));
+        account.apply(&Event::DisputeOpened(1, 12));
+        assert_eq!(account.available(), 30);
+        assert_eq!(account.held(), 12);
+        assert_eq!(account.total(), 42);
+        assert!(!account.is_locked());
+        account.apply(&Event::Locked(1));
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn replay() {
+        let mut account = Account::new();
+        let events = vec![
+            account.deposit(1, 42).unwrap(),
+            account.dispute(1, 12).unwrap(),
+            account.chargeback(1, 12).unwrap()
+        ];
+        assert_eq!(Account::replay(events), account);
+        assert!(account.is_locked());
+    }
+
     #[test]
     fn available() {
-        let mut account = Account::new(42);
-        account.amount_held = 32;
+        let account = account(42 - 32, 32);
         assert_eq!(account.available(), 42 - 32);
     }
 
     #[test]
     fn held() {
-        let mut account = Account::new(42);
-        account.amount_held = 12;
+        let account = account(42 - 12, 12);
         assert_eq!(account.held(), 12);
     }
 
     #[test]
     fn total() {
-        let mut account = Account::new(42);
-        account.amount_held = 12;
+        let account = account(42 - 12, 12);
         assert_eq!(account.total(), 42);
     }
 
     #[test]
     fn locked() {
-        let mut account = Account::new(42);
+        let mut account = account(42, 0);
         account.is_locked = true;
         assert_eq!(account.is_locked(), true);
     }
 
     #[test]
     fn deposit_normal() {
-        let mut account = Account::new(42);
-        let res = account.deposit(12);
+        let mut account = account(42, 0);
+        let res = account.deposit(1, 12);
         assert_eq!(account.available(), 42 + 12);
         assert_eq!(account.total(), 42 + 12);
-        assert_eq!(res, true);
+        assert_eq!(res, Ok(Event::Deposited(1, 12)));
     }
 
     #[test]
     fn deposit_locked() {
-        let mut account = Account::new(42);
+        let mut account = account(42, 0);
         account.is_locked = true;
-        let res = account.deposit(12);
+        let res = account.deposit(1, 12);
         assert_eq!(account.available(), 42);
         assert_eq!(account.total(), 42);
-        assert_eq!(res, false);
+        assert_eq!(res, Err(RejectReason::LockedAccount));
+    }
+
+    #[test]
+    fn deposit_overflow() {
+        let mut account = account(SignedAmount::MAX as Amount, 0);
+        assert!(account.deposit(2, SignedAmount::MAX as Amount).is_ok());
+        assert_eq!(account.total(), Amount::MAX - 1);
+        let res = account.deposit(3, 2);
+        assert_eq!(account.total(), Amount::MAX - 1);
+        assert_eq!(res, Err(RejectReason::Overflow));
     }
 
     #[test]
     fn withdraw_normal() {
-        let mut account = Account::new(42);
-        let res = account.withdraw(12);
+        let mut account = account(42, 0);
+        let res = account.withdraw(1, 12);
         assert_eq!(account.available(), 42 - 12);
         assert_eq!(account.total(), 42 - 12);
-        assert_eq!(res, true);
+        assert!(res.is_ok());
     }
 
     #[test]
     fn withdraw_locked() {
-        let mut account = Account::new(42);
+        let mut account = account(42, 0);
         account.is_locked = true;
-        let res = account.withdraw(12);
+        let res = account.withdraw(1, 12);
         assert_eq!(account.available(), 42);
         assert_eq!(account.total(), 42);
-        assert_eq!(res, false);
+        assert_eq!(res, Err(RejectReason::LockedAccount));
     }
 
     #[test]
     fn withdraw_insufficient_total_funds() {
-        let mut account = Account::new(42);
-        let res = account.withdraw(80);
+        let mut account = account(42, 0);
+        let res = account.withdraw(1, 80);
         assert_eq!(account.available(), 42);
         assert_eq!(account.total(), 42);
-        assert_eq!(res, false);
+        assert_eq!(res, Err(RejectReason::InsufficientFunds));
     }
 
     #[test]
     fn withdraw_insufficient_available_funds() {
-        let mut account = Account::new(42);
-        account.amount_held = 32;
-        let res = account.withdraw(40);
+        let mut account = account(42 - 32, 32);
+        let res = account.withdraw(1, 40);
         assert_eq!(account.available(), 42 - 32);
         assert_eq!(account.total(), 42);
-        assert_eq!(res, false);
+        assert_eq!(res, Err(RejectReason::InsufficientFunds));
     }
 
     #[test]
     fn dispute_normal() {
-        let mut account = Account::new(42);
-        let res = account.dispute(12);
+        let mut account = account(42, 0);
+        let res = account.dispute(1, 12);
         assert_eq!(account.available(), 30);
         assert_eq!(account.total(), 42);
-        assert_eq!(res, true);
+        assert!(res.is_ok());
     }
 
     #[test]
     fn dispute_insufficient_available_funds() {
-        let mut account = Account::new(42);
-        account.dispute(12);
-        let res = account.dispute(42);
+        let mut account = account(42, 0);
+        account.dispute(1, 12).unwrap();
+        let res = account.dispute(1, 42);
         assert_eq!(account.available(), 30);
         assert_eq!(account.total(), 42);
-        assert_eq!(res, false);
+        assert_eq!(res, Err(RejectReason::InsufficientFunds));
     }
 
     #[test]
     fn resolve_normal() {
-        let mut account = Account::new(42);
-        account.dispute(12);
-        let res = account.resolve(8);
+        let mut account = account(42, 0);
+        account.dispute(1, 12).unwrap();
+        let res = account.resolve(1, 8);
         assert_eq!(account.held(), 4);
-        assert_eq!(res, true);
+        assert!(res.is_ok());
     }
 
     #[test]
     fn resolve_insufficient_held_funds() {
-        let mut account = Account::new(42);
-        account.dispute(6);
-        let res = account.resolve(10);
+        let mut account = account(42, 0);
+        account.dispute(1, 6).unwrap();
+        let res = account.resolve(1, 10);
         assert_eq!(account.held(), 6);
-        assert_eq!(res, false);
+        assert_eq!(res, Err(RejectReason::InsufficientFunds));
     }
 
     #[test]
     fn chargeback_normal() {
-        let mut account = Account::new(42);
-        account.dispute(12);
-        let res = account.chargeback(12);
+        let mut account = account(42, 0);
+        account.dispute(1, 12).unwrap();
+        let res = account.chargeback(1, 12);
         assert_eq!(account.is_locked(), true);
         assert_eq!(account.available(), 30);
         assert_eq!(account.total(), 30);
-        assert_eq!(res, true);
+        assert!(res.is_ok());
     }
 
     #[test]
     fn chargeback_insufficient_held_funds() {
-        let mut account = Account::new(42);
-        let res = account.chargeback(12);
+        let mut account = account(42, 0);
+        let res = account.chargeback(1, 12);
         assert_eq!(account.is_locked(), false);
         assert_eq!(account.available(), 42);
         assert_eq!(account.total(), 42);
-        assert_eq!(res, false);
+        assert_eq!(res, Err(RejectReason::InsufficientFunds));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn dispute_withdrawal() {
+        let mut account = account(42, 0);
+        account.withdraw(1, 12).unwrap();
+        assert_eq!(account.dispute_withdrawal(1, 12), Ok(Event::WithdrawalDisputeOpened(1, 12)));
+        assert_eq!((account.available(), account.held()), (30, 12));
+        assert_eq!(account.resolve_withdrawal(1, 20), Err(RejectReason::InsufficientFunds));
+        assert_eq!(account.resolve_withdrawal(1, 2), Ok(Event::WithdrawalDisputeResolved(1, 2)));
+        assert_eq!(account.chargeback_withdrawal(1, 10), Ok(Event::WithdrawalChargedBack(1, 10)));
+        assert_eq!((account.available(), account.held()), (40, 0));
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn charge_fee() {
+        let mut account = account(42, 0);
+        assert_eq!(account.charge_fee(1, 2), Ok(Event::FeeCharged(1, 2)));
+        assert_eq!(account.available(), 40);
+        assert_eq!(account.charge_fee(1, 41), Err(RejectReason::InsufficientFunds));
+        assert_eq!(account.available(), 40);
+    }
+
+    #[test]
+    fn receive_interest() {
+        let mut account = account(42, 0);
+        assert_eq!(account.receive_interest(3), Ok(Event::InterestPaid(3)));
+        assert_eq!(account.total(), 45);
+    }
+
+    #[test]
+    fn reverse_deposit() {
+        let mut account = account(42, 0);
+        assert_eq!(account.reverse_deposit(1, 50), Err(RejectReason::InsufficientFunds));
+        assert_eq!(account.reverse_deposit(1, 42), Ok(Event::DepositReversed(1, 42)));
+        assert_eq!(account.total(), 0);
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn reverse_withdrawal() {
+        let mut account = account(42, 0);
+        account.withdraw(2, 12).unwrap();
+        assert_eq!(account.reverse_withdrawal(2, 12), Ok(Event::WithdrawalReversed(2, 12)));
+        assert_eq!(account.total(), 42);
+        account.is_locked = true;
+        assert_eq!(account.reverse_withdrawal(2, 12), Err(RejectReason::LockedAccount));
+    }
+
+    #[test]
+    fn refund_fee() {
+        let mut account = account(42, 0);
+        account.charge_fee(2, 3).unwrap();
+        assert_eq!(account.refund_fee(2, 3), Ok(Event::FeeRefunded(2, 3)));
+        assert_eq!(account.total(), 42);
+        account.is_locked = true;
+        assert_eq!(account.refund_fee(2, 3), Err(RejectReason::LockedAccount));
+    }
+}
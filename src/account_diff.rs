@@ -0,0 +1,272 @@
+// Compares two account outputs (client,available,held,total,locked, extra columns are ignored) client by client.
+// Amounts are compared as exact decimals, never through floating point.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use serde::Deserialize;
+use crate::common_types::ClientID;
+use crate::scale::{parse_decimal, DecimalError};
+
+// an exact decimal, `units` of 10^-`scale`
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    units: i128,
+    scale: u32
+}
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal{units: 0, scale: 0};
+
+    fn at_scale(self, scale: u32) -> i128 {
+        self.units * 10i128.pow(scale - self.scale)
+    }
+
+    pub fn abs(self) -> Self {
+        Self{units: self.units.abs(), ..self}
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.units < 0
+    }
+
+    // a tolerance is how far apart two amounts may be, which cannot be negative
+    pub fn parse_tolerance(tolerance: &str) -> Result<Self, String> {
+        let tolerance: Decimal = tolerance.parse()?;
+        if tolerance.is_negative() {
+            return Err(format!("tolerance '{}' is negative", tolerance));
+        }
+        Ok(tolerance)
+    }
+}
+
+impl std::ops::Sub for Decimal {
+    type Output = Decimal;
+
+    fn sub(self, other: Decimal) -> Decimal {
+        let scale = self.scale.max(other.scale);
+        Decimal{units: self.at_scale(scale) - other.at_scale(scale), scale}
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        self.at_scale(scale).cmp(&other.at_scale(scale))
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = String;
+
+    // parses a decimal such as "-12.5", without going through floating point
+    fn from_str(decimal: &str) -> Result<Self, Self::Err> {
+        let trimmed = decimal.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, trimmed)
+        };
+        let too_many_digits = || format!("decimal '{}' has too many digits", decimal);
+        let (digits, scale) = parse_decimal(digits).map_err(|e| match e {
+            DecimalError::Invalid => format!("invalid decimal '{}'", decimal),
+            DecimalError::TooManyDigits => too_many_digits()
+        })?;
+        if scale > 18 {
+            return Err(format!("decimal '{}' has too many decimals", decimal));
+        }
+        let units = i64::try_from(digits).map_err(|_| too_many_digits())? as i128;
+        Ok(Self {
+            units: if negative { -units } else { units },
+            scale
+        })
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = format!("{:0>width$}", self.units.unsigned_abs(), width = self.scale as usize + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        let fraction = fraction.trim_end_matches('0');
+        let sign = if self.units < 0 { "-" } else { "" };
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, integer)
+        } else {
+            write!(f, "{}{}.{}", sign, integer, fraction)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountRow {
+    pub client_id: ClientID,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool
+}
+
+#[derive(Debug, Deserialize)]
+struct Row {
+    client: ClientID,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool
+}
+
+pub fn parse_accounts_csv<P: AsRef<std::path::Path>>(path: P) -> Result<BTreeMap<ClientID, AccountRow>, Box<dyn Error>> {
+    parse_accounts_reader(std::fs::File::open(path)?)
+}
+
+pub fn parse_accounts_reader<R: std::io::Read>(input: R) -> Result<BTreeMap<ClientID, AccountRow>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+    let mut accounts = BTreeMap::new();
+    for row in reader.deserialize() {
+        let row: Row = row?;
+        let account = AccountRow {
+            client_id: row.client,
+            available: row.available.parse()?,
+            held: row.held.parse()?,
+            total: row.total.parse()?,
+            locked: row.locked
+        };
+        if accounts.insert(row.client, account).is_some() {
+            return Err(format!("client {} appears twice", row.client).into());
+        }
+    }
+    Ok(accounts)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientDiff {
+    Added(AccountRow),
+    Removed(AccountRow),
+    Changed(AccountRow, AccountRow)
+}
+
+// the difference of every amount, after minus before
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deltas {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal
+}
+
+impl ClientDiff {
+    pub fn client_id(&self) -> ClientID {
+        match self {
+            ClientDiff::Added(account) | ClientDiff::Removed(account) | ClientDiff::Changed(account, _) => account.client_id
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientDiff::Added(_) => "added",
+            ClientDiff::Removed(_) => "removed",
+            ClientDiff::Changed(..) => "changed"
+        }
+    }
+
+    pub fn before(&self) -> Option<&AccountRow> {
+        match self {
+            ClientDiff::Added(_) => None,
+            ClientDiff::Removed(before) | ClientDiff::Changed(before, _) => Some(before)
+        }
+    }
+
+    pub fn after(&self) -> Option<&AccountRow> {
+        match self {
+            ClientDiff::Removed(_) => None,
+            ClientDiff::Added(after) | ClientDiff::Changed(_, after) => Some(after)
+        }
+    }
+
+    // a missing account counts as zero
+    pub fn deltas(&self) -> Deltas {
+        let amounts = |account: Option<&AccountRow>| account.map_or((Decimal::ZERO, Decimal::ZERO, Decimal::ZERO),
+                                                                    |account| (account.available, account.held, account.total));
+        let (before, after) = (amounts(self.before()), amounts(self.after()));
+        Deltas {
+            available: after.0 - before.0,
+            held: after.1 - before.1,
+            total: after.2 - before.2
+        }
+    }
+
+    // an added or removed client and a lock change always exceed the tolerance
+    pub fn exceeds(&self, tolerance: Decimal) -> bool {
+        match self {
+            ClientDiff::Changed(before, after) if before.locked == after.locked => {
+                let deltas = self.deltas();
+                [deltas.available, deltas.held, deltas.total].iter().any(|delta| delta.abs() > tolerance)
+            },
+            _ => true
+        }
+    }
+}
+
+// every client that was added, removed or whose account changed, sorted by client
+pub fn diff_accounts(before: &BTreeMap<ClientID, AccountRow>, after: &BTreeMap<ClientID, AccountRow>) -> Vec<ClientDiff> {
+    let mut diffs: Vec<ClientDiff> = before.values()
+        .filter_map(|account| match after.get(&account.client_id) {
+            None => Some(ClientDiff::Removed(*account)),
+            Some(other) if other != account => Some(ClientDiff::Changed(*account, *other)),
+            Some(_) => None
+        })
+        .chain(after.values().filter(|account| !before.contains_key(&account.client_id)).map(|account| ClientDiff::Added(*account)))
+        .collect();
+    diffs.sort_by_key(|diff| diff.client_id());
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account_diff::{diff_accounts, parse_accounts_reader, ClientDiff, Decimal};
+
+    fn decimal(decimal: &str) -> Decimal {
+        decimal.parse().unwrap()
+    }
+
+    #[test]
+    fn decimals() {
+        assert_eq!(decimal("1.50"), decimal("1.5"));
+        assert_eq!((decimal("0.1") - decimal("0.3")).to_string(), "-0.2");
+        assert_eq!((decimal("3") - decimal("1.0001")).to_string(), "1.9999");
+        assert!(decimal("-0.5").abs() > decimal("0.4999"));
+        assert!("1e5".parse::<Decimal>().is_err());
+        assert!("".parse::<Decimal>().is_err());
+        assert_eq!(Decimal::parse_tolerance("0.01"), Ok(decimal("0.01")));
+        assert!(Decimal::parse_tolerance("-0.01").is_err());
+    }
+
+    #[test]
+    fn diff() {
+        let before = parse_accounts_reader("client,available,held,total,locked\n1,1.5,0,1.5,false\n2,3,0,3,false\n3,1,0,1,false\n".as_bytes()).unwrap();
+        let after = parse_accounts_reader("client,available,held,total,locked,flag\n1,1.5,0,1.5,false,\n2,2.9999,0,2.9999,false,\n4,1,0,1,true,\n".as_bytes()).unwrap();
+        let diffs = diff_accounts(&before, &after);
+        assert_eq!(diffs.iter().map(|diff| (diff.client_id(), diff.name())).collect::<Vec<_>>(),
+                   vec![(2, "changed"), (3, "removed"), (4, "added")]);
+        assert_eq!(diffs[0].deltas().total.to_string(), "-0.0001");
+        assert_eq!(diffs[1].deltas().available.to_string(), "-1");
+        assert!(!diffs[0].exceeds(decimal("0.0001")));
+        assert!(diffs[0].exceeds(Decimal::ZERO));
+        assert!(matches!(diffs[2], ClientDiff::Added(_)) && diffs[2].exceeds(decimal("10")));
+        assert!(parse_accounts_reader("client,available,held,total,locked\n1,1,0,1,false\n1,1,0,1,false\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use clap::Parser;
+use transaction_engine::generator::{generate, GeneratorConfig};
+use transaction_engine::scale::Scale;
+
+#[derive(Parser)]
+#[clap(author, version, about = "Generate a synthetic transaction input file", long_about=None)]
+struct Args {
+    /// Path to the output file, stdout if not set
+    #[clap(short, long, value_parser)]
+    output: Option<String>,
+
+    /// Number of rows to generate
+    #[clap(short, long, value_parser, default_value_t = 1_000_000)]
+    rows: u64,
+
+    /// Number of distinct clients
+    #[clap(short, long, value_parser, default_value_t = 1000)]
+    clients: u16,
+
+    /// Probability that a new transaction is a withdrawal
+    #[clap(long, value_parser, default_value_t = 0.3)]
+    withdrawal_ratio: f64,
+
+    /// Probability that a row opens a dispute, and that a row closes one
+    #[clap(long, value_parser, default_value_t = 0.02)]
+    dispute_ratio: f64,
+
+    /// Probability that a dispute ends in a chargeback instead of a resolve
+    #[clap(long, value_parser, default_value_t = 0.2)]
+    chargeback_ratio: f64,
+
+    /// Seed of the random generator
+    #[clap(long, value_parser, default_value_t = 42)]
+    seed: u64,
+
+    /// Decimals of the generated amounts
+    #[clap(long, value_parser, default_value_t = 4)]
+    decimals: u32
+}
+
+fn main() -> io::Result<()> {
+    let args = Args::parse();
+    let config = GeneratorConfig {
+        rows: args.rows,
+        clients: args.clients,
+        withdrawal_ratio: args.withdrawal_ratio,
+        dispute_ratio: args.dispute_ratio,
+        chargeback_ratio: args.chargeback_ratio,
+        seed: args.seed,
+        scale: Scale::new(args.decimals).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+    };
+
+    let mut writer: BufWriter<Box<dyn Write>> = match args.output {
+        Some(path) => BufWriter::new(Box::new(File::create(path)?)),
+        None => BufWriter::new(Box::new(io::stdout().lock()))
+    };
+    generate(&config, &mut writer)?;
+    writer.flush()
+}
@@ -1,12 +1,176 @@
+use std::fmt;
+
 pub type TransactionID = u32;
 pub type ClientID = u16;
+// units of the fixed point scale, see scale.rs
+#[cfg(not(feature = "wide-amounts"))]
 pub type Amount = u64;
+#[cfg(not(feature = "wide-amounts"))]
+pub type SignedAmount = i64;
+#[cfg(feature = "wide-amounts")]
+pub type Amount = u128;
+#[cfg(feature = "wide-amounts")]
+pub type SignedAmount = i128;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Transaction {
     Deposit(TransactionID, ClientID, Amount),
     Withdrawal(TransactionID, ClientID, Amount),
-    Dispute(TransactionID, ClientID),
-    Resolve(TransactionID, ClientID),
-    Chargeback(TransactionID, ClientID),
-}
\ No newline at end of file
+    // disputes, resolves and chargebacks apply to part of the deposit when they carry an amount, to all of it otherwise
+    Dispute(TransactionID, ClientID, Option<Amount>, Option<Box<DisputeInfo>>),
+    Resolve(TransactionID, ClientID, Option<Amount>),
+    Chargeback(TransactionID, ClientID, Option<Amount>),
+    // undoes an earlier deposit or withdrawal
+    Reversal(TransactionID, ClientID),
+}
+
+impl Transaction {
+    // the transaction of a type name, the dispute context is left out
+    pub fn new(name: &str, tx: TransactionID, cx: ClientID, amount: Option<Amount>) -> Result<Self, String> {
+        match name {
+            "deposit" => amount.map(|amount| Transaction::Deposit(tx, cx, amount))
+                .ok_or_else(|| "no amount for transaction type 'deposit'".to_string()),
+            "withdrawal" => amount.map(|amount| Transaction::Withdrawal(tx, cx, amount))
+                .ok_or_else(|| "no amount for transaction type 'withdrawal'".to_string()),
+            "dispute" => Ok(Transaction::Dispute(tx, cx, amount, None)),
+            "resolve" => Ok(Transaction::Resolve(tx, cx, amount)),
+            "chargeback" => Ok(Transaction::Chargeback(tx, cx, amount)),
+            "reversal" => Ok(Transaction::Reversal(tx, cx)),
+            _ => Err(format!("transaction type '{}' not supported", name))
+        }
+    }
+
+    pub fn tx(&self) -> TransactionID {
+        match *self {
+            Transaction::Deposit(tx, _, _) | Transaction::Withdrawal(tx, _, _) | Transaction::Dispute(tx, _, _, _)
+                | Transaction::Resolve(tx, _, _) | Transaction::Chargeback(tx, _, _) | Transaction::Reversal(tx, _) => tx
+        }
+    }
+
+    pub fn client(&self) -> ClientID {
+        match *self {
+            Transaction::Deposit(_, cx, _) | Transaction::Withdrawal(_, cx, _) | Transaction::Dispute(_, cx, _, _)
+                | Transaction::Resolve(_, cx, _) | Transaction::Chargeback(_, cx, _) | Transaction::Reversal(_, cx) => cx
+        }
+    }
+
+    pub fn amount(&self) -> Option<Amount> {
+        match *self {
+            Transaction::Deposit(_, _, amount) | Transaction::Withdrawal(_, _, amount) => Some(amount),
+            Transaction::Dispute(_, _, amount, _) | Transaction::Resolve(_, _, amount) | Transaction::Chargeback(_, _, amount) => amount,
+            Transaction::Reversal(..) => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transaction::Deposit(..) => "deposit",
+            Transaction::Withdrawal(..) => "withdrawal",
+            Transaction::Dispute(..) => "dispute",
+            Transaction::Resolve(..) => "resolve",
+            Transaction::Chargeback(..) => "chargeback",
+            Transaction::Reversal(..) => "reversal"
+        }
+    }
+}
+
+// context the card network gives about a dispute, kept as is
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DisputeInfo {
+    pub reason_code: Option<String>,
+    pub case_id: Option<String>,
+    pub deadline: Option<String>
+}
+
+// why the engine refused to apply a transaction
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RejectReason {
+    DuplicateTransaction,
+    UnknownAccount,
+    LockedAccount,
+    InsufficientFunds,
+    UnknownTransaction,
+    WrongClient,
+    AlreadyDisputed,
+    NotDisputed,
+    NotADeposit,
+    ExpiredTransaction,
+    ReversedTransaction,
+    // a partial amount of zero
+    InvalidAmount,
+    // a dispute for more than what is left undisputed of the deposit
+    ExceedsTransaction,
+    // a resolve or chargeback for more than what is held by the dispute
+    ExceedsDispute,
+    // would take a balance past the largest amount
+    Overflow,
+    // refused by the named risk rule
+    Rule(&'static str),
+    // over the named client limit
+    LimitExceeded(&'static str)
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RejectReason::DuplicateTransaction => "duplicate_transaction",
+            RejectReason::UnknownAccount => "unknown_account",
+            RejectReason::LockedAccount => "locked_account",
+            RejectReason::InsufficientFunds => "insufficient_funds",
+            RejectReason::UnknownTransaction => "unknown_transaction",
+            RejectReason::WrongClient => "wrong_client",
+            RejectReason::AlreadyDisputed => "already_disputed",
+            RejectReason::NotDisputed => "not_disputed",
+            RejectReason::NotADeposit => "not_a_deposit",
+            RejectReason::ExpiredTransaction => "expired_transaction",
+            RejectReason::ReversedTransaction => "reversed_transaction",
+            RejectReason::InvalidAmount => "invalid_amount",
+            RejectReason::ExceedsTransaction => "exceeds_transaction",
+            RejectReason::ExceedsDispute => "exceeds_dispute",
+            RejectReason::Overflow => "overflow",
+            RejectReason::Rule(rule) => return write!(f, "rule:{}", rule),
+            RejectReason::LimitExceeded(limit) => return write!(f, "limit:{}", limit)
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for RejectReason {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(match name {
+            "duplicate_transaction" => RejectReason::DuplicateTransaction,
+            "unknown_account" => RejectReason::UnknownAccount,
+            "locked_account" => RejectReason::LockedAccount,
+            "insufficient_funds" => RejectReason::InsufficientFunds,
+            "unknown_transaction" => RejectReason::UnknownTransaction,
+            "wrong_client" => RejectReason::WrongClient,
+            "already_disputed" => RejectReason::AlreadyDisputed,
+            "not_disputed" => RejectReason::NotDisputed,
+            "not_a_deposit" => RejectReason::NotADeposit,
+            "expired_transaction" => RejectReason::ExpiredTransaction,
+            "reversed_transaction" => RejectReason::ReversedTransaction,
+            "invalid_amount" => RejectReason::InvalidAmount,
+            "exceeds_transaction" => RejectReason::ExceedsTransaction,
+            "exceeds_dispute" => RejectReason::ExceedsDispute,
+            "overflow" => RejectReason::Overflow,
+            "rule:max_withdrawal" => RejectReason::Rule("max_withdrawal"),
+            "rule:max_daily_withdrawals" => RejectReason::Rule("max_daily_withdrawals"),
+            "rule:large_deposit_hold" => RejectReason::Rule("large_deposit_hold"),
+            "limit:max_deposit" => RejectReason::LimitExceeded("max_deposit"),
+            "limit:max_balance" => RejectReason::LimitExceeded("max_balance"),
+            "limit:max_withdrawals" => RejectReason::LimitExceeded("max_withdrawals"),
+            _ => return Err(format!("unknown rejection reason '{}'", name))
+        })
+    }
+}
+
+// a transaction the engine refused, `position` is the engine row it was processed at and `line` its line in the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub position: u64,
+    pub line: u64,
+    pub transaction: Transaction,
+    pub reason: RejectReason
+}
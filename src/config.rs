@@ -0,0 +1,239 @@
+// Settings of a deployment, read from a TOML file given with --config. Every setting is optional and the command line
+// options take precedence over the file:
+//
+// verbosity = 1
+// decimals = 8
+//
+// [input]
+// format = "csv"
+// negative_amounts = "reject"
+// delimiter = ";"
+//
+// [input.mapping]
+// transaction_id = "tx"
+//
+// [disputes]
+// withdrawals = true
+//
+// [limits]
+// max_balance = "100000.0"
+// file = "limits.csv"
+//
+// [fees]
+// withdrawal = "1.0"
+// withdrawal_rate = "0.01"
+//
+// [rules]
+// (the same tables as a risk rules file)
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::fees::Rate;
+use crate::parser;
+use crate::rules::RulesConfig;
+use crate::scale::{AmountSetting, Scale};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Csv
+}
+
+// what happens to a row with a negative amount
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NegativeAmounts {
+    // the amount is read as 0
+    #[default]
+    Zero,
+    // the row is skipped
+    Reject
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    pub format: Format,
+    pub negative_amounts: NegativeAmounts,
+    pub delimiter: char,
+    pub quote: char,
+    // without quoting the quote character is read as any other
+    pub quoting: bool,
+    // whether the first row names the columns
+    pub headers: bool,
+    // the names of the columns of a file without headers, in order
+    pub columns: Vec<String>,
+    // column name in the file -> field it holds, the columns holding no field are passed through to the rejections
+    pub mapping: BTreeMap<String, String>
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            format: Format::Csv,
+            negative_amounts: NegativeAmounts::Zero,
+            delimiter: ',',
+            quote: '"',
+            quoting: true,
+            headers: true,
+            columns: ["type", "client", "tx", "amount"].map(String::from).to_vec(),
+            mapping: BTreeMap::new()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub format: Format
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputesConfig {
+    // withdrawals can be disputed like deposits, their amount is held until the dispute is resolved or charged back
+    pub withdrawals: bool
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub rows: Option<u64>,
+    pub seconds: Option<u64>,
+    // how often the expired transactions are pruned, in rows
+    pub prune_interval: Option<u64>
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_balance: Option<AmountSetting>,
+    pub max_deposit: Option<AmountSetting>,
+    pub max_withdrawals: Option<u32>,
+    pub window_rows: Option<u64>,
+    pub window_seconds: Option<u64>,
+    // CSV of per-client limits overriding these defaults
+    pub file: Option<String>
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeesConfig {
+    pub withdrawal: Option<AmountSetting>,
+    pub withdrawal_rate: Option<Rate>,
+    pub chargeback: Option<AmountSetting>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterestConfig {
+    pub rate: Rate,
+    pub period_rows: u64
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub verbosity: u8,
+    // the fixed point scale of every amount read and written
+    pub decimals: Scale,
+    pub input: InputConfig,
+    pub output: OutputConfig,
+    pub disputes: DisputesConfig,
+    pub retention: RetentionConfig,
+    pub limits: LimitsConfig,
+    pub fees: FeesConfig,
+    pub interest: Option<InterestConfig>,
+    pub rules: Option<RulesConfig>
+}
+
+impl Config {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(content).map_err(|e| e.to_string())?;
+        config.check()?;
+        Ok(config)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        Self::parse(&std::fs::read_to_string(path).map_err(|e| e.to_string())?)
+    }
+
+    // the settings the command line would refuse together
+    pub fn check(&self) -> Result<(), String> {
+        if self.retention.rows.is_some() && self.retention.seconds.is_some() {
+            return Err("retention rows and seconds cannot both be set".to_string());
+        }
+        if self.retention.prune_interval == Some(0) {
+            return Err("retention prune_interval must be at least 1".to_string());
+        }
+        if self.limits.window_rows.is_some() && self.limits.window_seconds.is_some() {
+            return Err("limits window_rows and window_seconds cannot both be set".to_string());
+        }
+        if self.interest.as_ref().is_some_and(|interest| interest.period_rows == 0) {
+            return Err("interest period_rows must be at least 1".to_string());
+        }
+        if !self.input.delimiter.is_ascii() || !self.input.quote.is_ascii() {
+            return Err("input delimiter and quote must be ASCII characters".to_string());
+        }
+        if let Some(field) = self.input.mapping.values().find(|field| !parser::FIELDS.contains(&field.as_str())) {
+            return Err(format!("input mapping to unknown field '{}', the fields are {}", field, parser::FIELDS.join(", ")));
+        }
+        // the amounts are read at the scale of the config
+        let amounts = [
+            ("limits max_balance", &self.limits.max_balance),
+            ("limits max_deposit", &self.limits.max_deposit),
+            ("fees withdrawal", &self.fees.withdrawal),
+            ("fees chargeback", &self.fees.chargeback)
+        ];
+        for (name, amount) in amounts {
+            if let Some(amount) = amount {
+                amount.to_amount(self.decimals).map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+        if let Some(rules) = &self.rules {
+            rules.amounts(self.decimals).map_err(|e| format!("rules {}", e))?;
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string(self).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, NegativeAmounts};
+
+    #[test]
+    fn parse() {
+        let config = Config::parse("verbosity = 2\ndecimals = 2\n[input]\nnegative_amounts = \"reject\"\n[disputes]\nwithdrawals = true\n\
+                                    [fees]\nwithdrawal_rate = \"0.01\"\n[rules.max_withdrawal]\namount = \"10.0\"\n").unwrap();
+        assert_eq!(config.verbosity, 2);
+        assert_eq!(config.decimals.decimals(), 2);
+        assert_eq!(config.input.negative_amounts, NegativeAmounts::Reject);
+        assert!(config.disputes.withdrawals);
+        assert_eq!(config.fees.withdrawal_rate.unwrap().to_string(), "0.01");
+        assert_eq!(config.rules.as_ref().unwrap().max_withdrawal.as_ref().unwrap().amount.to_amount(config.decimals), Ok(1000));
+        assert_eq!(Config::parse(&config.to_toml().unwrap()), Ok(config));
+        assert_eq!(Config::parse(""), Ok(Config::default()));
+        assert!(Config::parse("[input]\nformat = \"json\"\n").is_err());
+        assert!(Config::parse("[retention]\nrows = 1\nseconds = 1\n").is_err());
+        assert!(Config::parse("[retention]\nprune_interval = 0\n").is_err());
+        assert!(Config::parse("precision = 4\n").is_err());
+        assert!(Config::parse("decimals = 40\n").is_err());
+        assert!(Config::parse("[limits]\nmax_balance = 100.0\n").is_err());
+        assert!(Config::parse("decimals = 2\n[fees]\nwithdrawal = \"0.001\"\n").is_err());
+        assert!(Config::parse("decimals = 2\n[rules.large_deposit_hold]\namount = \"0.001\"\nrows = 1\n").is_err());
+
+        let config = Config::parse("[input]\ndelimiter = \";\"\nheaders = false\ncolumns = [\"tx\", \"type\"]\n\
+                                    [input.mapping]\ntransaction_id = \"tx\"\n").unwrap();
+        assert_eq!(config.input.delimiter, ';');
+        assert_eq!(config.input.mapping["transaction_id"], "tx");
+        assert_eq!(Config::parse(&config.to_toml().unwrap()), Ok(config));
+        assert!(Config::parse("[input.mapping]\nid = \"transaction\"\n").is_err());
+        assert!(Config::parse("[input]\ndelimiter = \"é\"\n").is_err());
+    }
+}
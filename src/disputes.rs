@@ -0,0 +1,111 @@
+// Dispute cases: one per disputed deposit, from its first dispute until nothing is held by its disputes anymore.
+// They keep the reason code, network case ID and response deadline given with the disputes.
+
+use std::fmt;
+use crate::common_types::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseStatus {
+    Open,
+    Resolved,
+    // at least part of the deposit was charged back
+    ChargedBack
+}
+
+impl fmt::Display for CaseStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CaseStatus::Open => "open",
+            CaseStatus::Resolved => "resolved",
+            CaseStatus::ChargedBack => "charged_back"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for CaseStatus {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "open" => Ok(CaseStatus::Open),
+            "resolved" => Ok(CaseStatus::Resolved),
+            "charged_back" => Ok(CaseStatus::ChargedBack),
+            _ => Err(format!("unknown case status '{}'", name))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisputeCase {
+    pub tx: TransactionID,
+    pub client_id: ClientID,
+    // input line of the first dispute
+    pub opened: u64,
+    pub status: CaseStatus,
+    // total amount disputed during the case and the part of it that was charged back
+    pub disputed: Amount,
+    pub charged_back: Amount,
+    pub info: DisputeInfo
+}
+
+impl DisputeCase {
+    pub fn open(tx: TransactionID, client_id: ClientID, opened: u64) -> Self {
+        Self {
+            tx,
+            client_id,
+            opened,
+            status: CaseStatus::Open,
+            disputed: 0,
+            charged_back: 0,
+            info: DisputeInfo::default()
+        }
+    }
+
+    // a later dispute on the same deposit adds to the case, the fields it gives replace the previous ones
+    pub fn dispute(&mut self, amount: Amount, info: Option<&DisputeInfo>) {
+        self.disputed += amount;
+        if let Some(info) = info {
+            self.info = DisputeInfo {
+                reason_code: info.reason_code.clone().or(self.info.reason_code.take()),
+                case_id: info.case_id.clone().or(self.info.case_id.take()),
+                deadline: info.deadline.clone().or(self.info.deadline.take())
+            };
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.status = if self.charged_back > 0 { CaseStatus::ChargedBack } else { CaseStatus::Resolved };
+    }
+}
+
+// a deposit still under dispute, `amount` is what its disputes hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenDispute {
+    pub tx: TransactionID,
+    pub client_id: ClientID,
+    pub amount: Amount,
+    // input line where the dispute was opened
+    pub opened: u64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common_types::DisputeInfo;
+    use crate::disputes::{CaseStatus, DisputeCase};
+
+    #[test]
+    fn merge_info() {
+        let mut case = DisputeCase::open(1, 1, 1);
+        case.dispute(10, Some(&DisputeInfo{reason_code: Some("4837".to_string()), case_id: Some("A1".to_string()), deadline: None}));
+        case.dispute(5, Some(&DisputeInfo{reason_code: None, case_id: Some("A2".to_string()), deadline: Some("2024-06-01".to_string())}));
+        case.dispute(5, None);
+        assert_eq!(case.disputed, 20);
+        assert_eq!(case.info, DisputeInfo{reason_code: Some("4837".to_string()), case_id: Some("A2".to_string()), deadline: Some("2024-06-01".to_string())});
+        case.close();
+        assert_eq!(case.status, CaseStatus::Resolved);
+        case.charged_back = 5;
+        case.close();
+        assert_eq!(case.status, CaseStatus::ChargedBack);
+    }
+}
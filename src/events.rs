@@ -0,0 +1,167 @@
+// Everything that happened to a client account, in order. An account is the fold of its events (see `Account::replay`)
+// and the ledger postings are derived from them.
+
+use std::fmt;
+use crate::common_types::*;
+use crate::account::Account;
+use crate::ledger::{Book, EntryKind, Posting};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Deposited(TransactionID, Amount),
+    Withdrew(TransactionID, Amount),
+    DisputeOpened(TransactionID, Amount),
+    DisputeResolved(TransactionID, Amount),
+    // also locks the account
+    ChargedBack(TransactionID, Amount),
+    FeeCharged(TransactionID, Amount),
+    // interest belongs to no input transaction
+    InterestPaid(Amount),
+    DepositReversed(TransactionID, Amount),
+    WithdrawalReversed(TransactionID, Amount),
+    // the fee of a reversed withdrawal goes back to the client
+    FeeRefunded(TransactionID, Amount),
+    // a disputed withdrawal is held back from the settlement, the available funds are left alone
+    WithdrawalDisputeOpened(TransactionID, Amount),
+    // the withdrawal stands, the held amount goes back to the settlement
+    WithdrawalDisputeResolved(TransactionID, Amount),
+    // the withdrawal is undone, the held amount is credited back to the client, also locks the account
+    WithdrawalChargedBack(TransactionID, Amount),
+    // locked without a chargeback, by the fraud heuristics on the given transaction
+    Locked(TransactionID)
+}
+
+impl Event {
+    // rebuilds an event from its name, as exported or stored
+    pub fn new(name: &str, tx: Option<TransactionID>, amount: Amount) -> Result<Self, String> {
+        if name == "interest_paid" {
+            return Ok(Event::InterestPaid(amount));
+        }
+        let tx = tx.ok_or_else(|| format!("no transaction for event '{}'", name))?;
+        Ok(match name {
+            "deposited" => Event::Deposited(tx, amount),
+            "withdrew" => Event::Withdrew(tx, amount),
+            "dispute_opened" => Event::DisputeOpened(tx, amount),
+            "dispute_resolved" => Event::DisputeResolved(tx, amount),
+            "charged_back" => Event::ChargedBack(tx, amount),
+            "fee_charged" => Event::FeeCharged(tx, amount),
+            "deposit_reversed" => Event::DepositReversed(tx, amount),
+            "withdrawal_reversed" => Event::WithdrawalReversed(tx, amount),
+            "fee_refunded" => Event::FeeRefunded(tx, amount),
+            "withdrawal_dispute_opened" => Event::WithdrawalDisputeOpened(tx, amount),
+            "withdrawal_dispute_resolved" => Event::WithdrawalDisputeResolved(tx, amount),
+            "withdrawal_charged_back" => Event::WithdrawalChargedBack(tx, amount),
+            "locked" => Event::Locked(tx),
+            _ => return Err(format!("unknown event '{}'", name))
+        })
+    }
+
+    pub fn tx(&self) -> Option<TransactionID> {
+        match *self {
+            Event::Deposited(tx, _) | Event::Withdrew(tx, _) | Event::DisputeOpened(tx, _) | Event::DisputeResolved(tx, _)
+                | Event::ChargedBack(tx, _) | Event::FeeCharged(tx, _)
+                | Event::DepositReversed(tx, _) | Event::WithdrawalReversed(tx, _) | Event::FeeRefunded(tx, _) | Event::WithdrawalDisputeOpened(tx, _)
+                | Event::WithdrawalDisputeResolved(tx, _) | Event::WithdrawalChargedBack(tx, _) | Event::Locked(tx) => Some(tx),
+            Event::InterestPaid(_) => None
+        }
+    }
+
+    // the ledger posting of the event, none if it moves no funds
+    pub fn posting(&self) -> Option<Posting> {
+        let (tx, from, to, amount) = match *self {
+            Event::Deposited(tx, amount) => (tx, Book::Settlement, Book::Available, amount),
+            Event::Withdrew(tx, amount) => (tx, Book::Available, Book::Settlement, amount),
+            Event::DisputeOpened(tx, amount) => (tx, Book::Available, Book::Held, amount),
+            Event::DisputeResolved(tx, amount) => (tx, Book::Held, Book::Available, amount),
+            Event::ChargedBack(tx, amount) => (tx, Book::Held, Book::ChargebackLoss, amount),
+            Event::FeeCharged(tx, amount) => (tx, Book::Available, Book::House, amount),
+            Event::InterestPaid(amount) => return Some(Posting::untied(Book::House, Book::Available, amount)),
+            Event::DepositReversed(tx, amount) => (tx, Book::Available, Book::Reversal, amount),
+            Event::WithdrawalReversed(tx, amount) => (tx, Book::Reversal, Book::Available, amount),
+            // the same books as interest, it is part of the reversal
+            Event::FeeRefunded(tx, amount) =>
+                return Some(Posting::new(tx, Book::House, Book::Available, amount).with_kind(EntryKind::Reversal)),
+            Event::WithdrawalDisputeOpened(tx, amount) => (tx, Book::Settlement, Book::Held, amount),
+            Event::WithdrawalDisputeResolved(tx, amount) => (tx, Book::Held, Book::Settlement, amount),
+            // the same books as a resolve of a deposit dispute
+            Event::WithdrawalChargedBack(tx, amount) =>
+                return Some(Posting::new(tx, Book::Held, Book::Available, amount).with_kind(EntryKind::Chargeback)),
+            Event::Locked(_) => return None
+        };
+        Some(Posting::new(tx, from, to, amount))
+    }
+
+    pub fn amount(&self) -> Option<Amount> {
+        self.posting().map(|posting| posting.amount)
+    }
+
+    pub fn locks(&self) -> bool {
+        matches!(self, Event::ChargedBack(..) | Event::WithdrawalChargedBack(..) | Event::Locked(_))
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Event::Deposited(..) => "deposited",
+            Event::Withdrew(..) => "withdrew",
+            Event::DisputeOpened(..) => "dispute_opened",
+            Event::DisputeResolved(..) => "dispute_resolved",
+            Event::ChargedBack(..) => "charged_back",
+            Event::FeeCharged(..) => "fee_charged",
+            Event::InterestPaid(..) => "interest_paid",
+            Event::DepositReversed(..) => "deposit_reversed",
+            Event::WithdrawalReversed(..) => "withdrawal_reversed",
+            Event::FeeRefunded(..) => "fee_refunded",
+            Event::WithdrawalDisputeOpened(..) => "withdrawal_dispute_opened",
+            Event::WithdrawalDisputeResolved(..) => "withdrawal_dispute_resolved",
+            Event::WithdrawalChargedBack(..) => "withdrawal_charged_back",
+            Event::Locked(_) => "locked"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// every event of a client, in order, with the account state right after it
+pub fn history<I: IntoIterator<Item=Event>>(events: I) -> impl Iterator<Item=(Event, Account)> {
+    let mut account = Account::new();
+    events.into_iter().map(move |event| {
+        account.apply(&event);
+        (event, account)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common_types::Amount;
+    use crate::events::{history, Event};
+    use crate::ledger::{Book, EntryKind, Posting};
+
+    #[test]
+    fn names() {
+        let events = [Event::Deposited(1, 2), Event::DisputeOpened(1, 2), Event::ChargedBack(1, 2), Event::WithdrawalChargedBack(1, 2),
+                      Event::InterestPaid(2), Event::Locked(3)];
+        for event in events {
+            assert_eq!(Event::new(&event.to_string(), event.tx(), event.amount().unwrap_or(0)), Ok(event));
+        }
+        assert!(Event::new("posted", Some(1), 2).is_err());
+        assert!(Event::new("deposited", None, 2).is_err());
+    }
+
+    #[test]
+    fn postings() {
+        assert_eq!(Event::ChargedBack(1, 2).posting(), Some(Posting::new(1, Book::Held, Book::ChargebackLoss, 2)));
+        assert_eq!(Event::WithdrawalDisputeOpened(1, 2).posting().unwrap().kind(), EntryKind::Dispute);
+        assert_eq!(Event::WithdrawalChargedBack(1, 2).posting().unwrap().kind(), EntryKind::Chargeback);
+        assert_eq!(Event::Locked(1).posting(), None);
+    }
+
+    #[test]
+    fn account_history() {
+        let events = vec![Event::Deposited(1, 42), Event::DisputeOpened(1, 12), Event::Locked(1)];
+        let states: Vec<(Amount, Amount, bool)> = history(events)
+            .map(|(_, account)| (account.available(), account.held(), account.is_locked()))
+            .collect();
+        assert_eq!(states, vec![(42, 0, false), (30, 12, false), (30, 12, true)]);
+    }
+}
@@ -0,0 +1,136 @@
+// Fees charged to the clients and interest paid to them, both booked against the House book.
+// Rates are exact decimals so percentages are computed without floating point errors.
+
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use crate::common_types::Amount;
+use crate::scale::{parse_decimal, DecimalError};
+
+// an exact decimal rate, `numerator / 10^scale`, written as a string in config files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rate {
+    numerator: u64,
+    scale: u32
+}
+
+impl Rate {
+    // `amount * rate`, rounded half to even
+    pub fn apply(&self, amount: Amount) -> Amount {
+        // Amount is already u128 with wide amounts, where the product may saturate
+        #[allow(clippy::useless_conversion)]
+        let product = u128::from(amount).saturating_mul(u128::from(self.numerator));
+        let denominator = 10u128.pow(self.scale);
+        let (quotient, remainder) = (product / denominator, product % denominator);
+        let rounded = match (remainder * 2).cmp(&denominator) {
+            std::cmp::Ordering::Less => quotient,
+            std::cmp::Ordering::Greater => quotient + 1,
+            std::cmp::Ordering::Equal => quotient + quotient % 2
+        };
+        Amount::try_from(rounded).unwrap_or(Amount::MAX)
+    }
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    // parses a decimal such as "0.015", without going through floating point
+    fn from_str(rate: &str) -> Result<Self, Self::Err> {
+        let too_many_digits = || format!("rate '{}' has too many digits", rate);
+        let (digits, scale) = parse_decimal(rate.trim()).map_err(|e| match e {
+            DecimalError::Invalid => format!("invalid rate '{}'", rate),
+            DecimalError::TooManyDigits => too_many_digits()
+        })?;
+        if scale > 18 {
+            return Err(format!("rate '{}' has too many decimals", rate));
+        }
+        Ok(Self {
+            numerator: u64::try_from(digits).map_err(|_| too_many_digits())?,
+            scale
+        })
+    }
+}
+
+impl TryFrom<String> for Rate {
+    type Error = String;
+
+    fn try_from(rate: String) -> Result<Self, Self::Error> {
+        rate.parse()
+    }
+}
+
+impl From<Rate> for String {
+    fn from(rate: Rate) -> Self {
+        rate.to_string()
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = format!("{:0>width$}", self.numerator, width = self.scale as usize + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        if fraction.is_empty() {
+            write!(f, "{}", integer)
+        } else {
+            write!(f, "{}.{}", integer, fraction)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeSchedule {
+    // charged on every withdrawal on top of the withdrawn amount
+    pub withdrawal_flat: Amount,
+    pub withdrawal_rate: Option<Rate>,
+    // charged from the available funds after a chargeback, as much as they cover
+    pub chargeback: Amount
+}
+
+impl FeeSchedule {
+    pub fn withdrawal_fee(&self, amount: Amount) -> Amount {
+        self.withdrawal_flat + self.withdrawal_rate.map_or(0, |rate| rate.apply(amount))
+    }
+}
+
+// interest on the available funds of every unlocked account, paid every `period_rows` input rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterestPolicy {
+    pub rate: Rate,
+    pub period_rows: u64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common_types::Amount;
+    use crate::fees::{FeeSchedule, Rate};
+
+    #[test]
+    fn parse_rate() {
+        assert_eq!("0.015".parse::<Rate>().unwrap().to_string(), "0.015");
+        assert_eq!("2".parse::<Rate>().unwrap().to_string(), "2");
+        assert_eq!(".5".parse::<Rate>().unwrap().to_string(), "0.5");
+        assert!("1e-3".parse::<Rate>().is_err());
+        assert!("-0.1".parse::<Rate>().is_err());
+        assert!(".".parse::<Rate>().is_err());
+    }
+
+    #[test]
+    fn apply_rounds_half_to_even() {
+        let rate: Rate = "0.5".parse().unwrap();
+        assert_eq!(rate.apply(10), 5);
+        assert_eq!(rate.apply(5), 2);
+        assert_eq!(rate.apply(7), 4);
+        let rate: Rate = "0.0001".parse().unwrap();
+        assert_eq!(rate.apply(14999), 1);
+        assert_eq!(rate.apply(15001), 2);
+        assert_eq!(rate.apply(u64::MAX as Amount), 1844674407370955);
+    }
+
+    #[test]
+    fn withdrawal_fee() {
+        let fees = FeeSchedule{withdrawal_flat: 100, withdrawal_rate: Some("0.01".parse().unwrap()), chargeback: 0};
+        assert_eq!(fees.withdrawal_fee(10000), 200);
+        assert_eq!(FeeSchedule::default().withdrawal_fee(10000), 0);
+    }
+}
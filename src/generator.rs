@@ -0,0 +1,130 @@
+// synthetic input generator, produces files in the `test_inputs` format
+
+use std::io::{self, Write};
+use crate::common_types::Amount;
+use crate::scale::Scale;
+
+pub struct GeneratorConfig {
+    pub rows: u64,
+    pub clients: u16,
+    pub withdrawal_ratio: f64,
+    pub dispute_ratio: f64,
+    pub chargeback_ratio: f64,
+    pub seed: u64,
+    // the amounts are written with its decimals
+    pub scale: Scale
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            rows: 1_000_000,
+            clients: 1000,
+            withdrawal_ratio: 0.3,
+            dispute_ratio: 0.02,
+            chargeback_ratio: 0.2,
+            seed: 42,
+            scale: Scale::default()
+        }
+    }
+}
+
+// splitmix64, good enough for test data and keeps the output reproducible from the seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+// between the smallest unit of the scale and `whole`
+// Amount is only wider than u64 with wide amounts
+#[allow(clippy::useless_conversion)]
+fn random_amount(rng: &mut Rng, scale: Scale, whole: u64) -> Amount {
+    let max = u64::try_from(scale.factor()).map_or(u64::MAX, |factor| factor.saturating_mul(whole));
+    Amount::from(rng.below(max) + 1)
+}
+
+pub fn generate<W: Write>(config: &GeneratorConfig, writer: &mut W) -> io::Result<()> {
+    let mut rng = Rng(config.seed);
+    let clients = config.clients.max(1) as u64;
+    // deposits that can still be disputed and disputes waiting for a resolve or a chargeback
+    let mut deposits: Vec<(u32, u16)> = Vec::new();
+    let mut disputes: Vec<(u32, u16)> = Vec::new();
+    let mut next_tx: u32 = 1;
+
+    writeln!(writer, "type,client,tx,amount")?;
+    for _ in 0..config.rows {
+        if !disputes.is_empty() && rng.chance(config.dispute_ratio) {
+            let (tx, cx) = disputes.swap_remove(rng.below(disputes.len() as u64) as usize);
+            let kind = if rng.chance(config.chargeback_ratio) { "chargeback" } else { "resolve" };
+            writeln!(writer, "{},{},{},", kind, cx, tx)?;
+        } else if !deposits.is_empty() && rng.chance(config.dispute_ratio) {
+            let (tx, cx) = deposits.swap_remove(rng.below(deposits.len() as u64) as usize);
+            disputes.push((tx, cx));
+            writeln!(writer, "dispute,{},{},", cx, tx)?;
+        } else {
+            let cx = rng.below(clients) as u16 + 1;
+            let tx = next_tx;
+            next_tx = next_tx.wrapping_add(1);
+            if rng.chance(config.withdrawal_ratio) {
+                let amount = random_amount(&mut rng, config.scale, 50);
+                write!(writer, "withdrawal,{},{},{}", cx, tx, config.scale.format(amount))?;
+            } else {
+                deposits.push((tx, cx));
+                let amount = random_amount(&mut rng, config.scale, 100);
+                write!(writer, "deposit,{},{},{}", cx, tx, config.scale.format(amount))?;
+            }
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::generator::{generate, GeneratorConfig};
+    use crate::parser::{parse_reader, parse_reader_with};
+    use crate::scale::Scale;
+
+    #[test]
+    fn generate_parsable_rows() {
+        let config = GeneratorConfig{rows: 1000, clients: 10, ..GeneratorConfig::default()};
+        let mut output = Vec::new();
+        generate(&config, &mut output).unwrap();
+        assert_eq!(parse_reader(output.as_slice()).unwrap().len(), 1000);
+    }
+
+    #[test]
+    fn generate_reproducible() {
+        let config = GeneratorConfig{rows: 100, ..GeneratorConfig::default()};
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        generate(&config, &mut first).unwrap();
+        generate(&config, &mut second).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn generate_at_scale() {
+        let config = GeneratorConfig{rows: 100, scale: Scale::new(2).unwrap(), ..GeneratorConfig::default()};
+        let mut output = Vec::new();
+        generate(&config, &mut output).unwrap();
+        let config = Config{decimals: config.scale, ..Config::default()};
+        assert_eq!(parse_reader_with(output.as_slice(), &config).unwrap().len(), 100);
+    }
+}
@@ -0,0 +1,140 @@
+// Fraud heuristics on the dispute history of each client, configured in the risk rules file:
+//
+// [dispute_frequency]
+// disputes = 3
+// transactions = 10
+// action = "lock"
+//
+// [dispute_ratio]
+// ratio = 0.5
+// min_deposits = 4
+// action = "flag"
+
+use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeuristicAction {
+    #[default]
+    Flag,
+    Lock
+}
+
+// at least `disputes` disputes among the last `transactions` transactions of a client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DisputeFrequency {
+    pub disputes: usize,
+    pub transactions: usize,
+    #[serde(default)]
+    pub action: HeuristicAction
+}
+
+// disputes over deposits above `ratio`, once a client made at least `min_deposits` deposits. The engine never lifts a
+// flag, so a ratio going back under the threshold does not need a second, lower one to keep it from flapping
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DisputeRatio {
+    pub ratio: f64,
+    #[serde(default = "default_min_deposits")]
+    pub min_deposits: u64,
+    #[serde(default)]
+    pub action: HeuristicAction
+}
+
+fn default_min_deposits() -> u64 {
+    1
+}
+
+// why a client was flagged, `locked` if the heuristic also locked the account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flag {
+    pub reason: String,
+    pub locked: bool
+}
+
+// what the heuristics know about a client
+#[derive(Debug, Clone, Default)]
+pub struct DisputeHistory {
+    // whether each of the last transactions was a dispute, most recent last
+    recent: VecDeque<bool>,
+    deposits: u64,
+    disputes: u64
+}
+
+impl DisputeHistory {
+    pub fn record_deposit(&mut self, window: usize) {
+        self.deposits += 1;
+        self.push(false, window);
+    }
+
+    pub fn record_dispute(&mut self, window: usize) {
+        self.disputes += 1;
+        self.push(true, window);
+    }
+
+    pub fn record_other(&mut self, window: usize) {
+        self.push(false, window);
+    }
+
+    fn push(&mut self, is_dispute: bool, window: usize) {
+        self.recent.push_back(is_dispute);
+        while self.recent.len() > window {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn check_frequency(&self, heuristic: &DisputeFrequency) -> Option<Flag> {
+        let disputes = self.recent.iter().filter(|is_dispute| **is_dispute).count();
+        (disputes >= heuristic.disputes).then(|| Flag {
+            reason: format!("dispute_frequency:{}/{}", disputes, heuristic.transactions),
+            locked: heuristic.action == HeuristicAction::Lock
+        })
+    }
+
+    pub fn check_ratio(&self, heuristic: &DisputeRatio) -> Option<Flag> {
+        if self.deposits == 0 || self.deposits < heuristic.min_deposits {
+            return None;
+        }
+        let ratio = self.disputes as f64 / self.deposits as f64;
+        (ratio > heuristic.ratio).then(|| Flag {
+            reason: format!("dispute_ratio:{:.2}", ratio),
+            locked: heuristic.action == HeuristicAction::Lock
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heuristics::{DisputeFrequency, DisputeHistory, DisputeRatio, Flag, HeuristicAction};
+
+    #[test]
+    fn frequency() {
+        let heuristic = DisputeFrequency{disputes: 2, transactions: 3, action: HeuristicAction::Lock};
+        let mut history = DisputeHistory::default();
+        history.record_deposit(3);
+        history.record_dispute(3);
+        assert_eq!(history.check_frequency(&heuristic), None);
+        history.record_other(3);
+        history.record_other(3);
+        history.record_dispute(3);
+        // the first dispute left the window
+        assert_eq!(history.check_frequency(&heuristic), None);
+        history.record_dispute(3);
+        assert_eq!(history.check_frequency(&heuristic), Some(Flag{reason: "dispute_frequency:2/3".to_string(), locked: true}));
+    }
+
+    #[test]
+    fn ratio() {
+        let heuristic = DisputeRatio{ratio: 0.5, min_deposits: 2, action: HeuristicAction::Flag};
+        let mut history = DisputeHistory::default();
+        history.record_deposit(10);
+        history.record_dispute(10);
+        assert_eq!(history.check_ratio(&heuristic), None);
+        history.record_deposit(10);
+        assert_eq!(history.check_ratio(&heuristic), None);
+        history.record_dispute(10);
+        assert_eq!(history.check_ratio(&heuristic), Some(Flag{reason: "dispute_ratio:1.00".to_string(), locked: false}));
+    }
+}
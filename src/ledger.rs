@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::common_types::*;
+use crate::events::Event;
+
+// Available and Held belong to the client, Settlement and ChargebackLoss are the external counterparts,
+// House collects the fees and pays the interest, Reversal offsets the reversed deposits and withdrawals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Book {
+    Available,
+    Held,
+    Settlement,
+    ChargebackLoss,
+    House,
+    Reversal
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Fee,
+    Interest,
+    Reversal,
+    Other
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            EntryKind::Deposit => "deposit",
+            EntryKind::Withdrawal => "withdrawal",
+            EntryKind::Dispute => "dispute",
+            EntryKind::Resolve => "resolve",
+            EntryKind::Chargeback => "chargeback",
+            EntryKind::Fee => "fee",
+            EntryKind::Interest => "interest",
+            EntryKind::Reversal => "reversal",
+            EntryKind::Other => "other"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// moves `amount` from one book to another, every posting is balanced by construction. `tx` is none for the postings
+// that belong to no input transaction, such as the interest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Posting {
+    pub tx: Option<TransactionID>,
+    pub from: Book,
+    pub to: Book,
+    pub amount: Amount,
+    kind: EntryKind
+}
+
+impl Posting {
+    pub fn new(tx: TransactionID, from: Book, to: Book, amount: Amount) -> Self {
+        Self {
+            tx: Some(tx),
+            ..Self::untied(from, to, amount)
+        }
+    }
+
+    pub fn untied(from: Book, to: Book, amount: Amount) -> Self {
+        Self {
+            tx: None,
+            from,
+            to,
+            amount,
+            kind: Self::kind_of(from, to)
+        }
+    }
+
+    // for the postings whose books alone do not tell what they are
+    pub fn with_kind(self, kind: EntryKind) -> Self {
+        Self {
+            kind,
+            ..self
+        }
+    }
+
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+
+    fn kind_of(from: Book, to: Book) -> EntryKind {
+        match (from, to) {
+            (Book::Settlement, Book::Available) => EntryKind::Deposit,
+            (Book::Available, Book::Settlement) => EntryKind::Withdrawal,
+            (Book::Available, Book::Held) | (Book::Settlement, Book::Held) => EntryKind::Dispute,
+            (Book::Held, Book::Available) | (Book::Held, Book::Settlement) => EntryKind::Resolve,
+            (Book::Held, Book::ChargebackLoss) => EntryKind::Chargeback,
+            (Book::Available, Book::House) => EntryKind::Fee,
+            (Book::House, Book::Available) => EntryKind::Interest,
+            (Book::Available, Book::Reversal) | (Book::Reversal, Book::Available) => EntryKind::Reversal,
+            _ => EntryKind::Other
+        }
+    }
+
+    // signed effect of the posting on the given book, wrapping like the book balances
+    pub fn delta(&self, book: Book) -> SignedAmount {
+        let mut delta: SignedAmount = 0;
+        if self.to == book {
+            delta = delta.wrapping_add(self.amount as SignedAmount);
+        }
+        if self.from == book {
+            delta = delta.wrapping_sub(self.amount as SignedAmount);
+        }
+        delta
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balance {
+    pub available: Amount,
+    pub held: Amount
+}
+
+impl Balance {
+    pub fn total(&self) -> Amount {
+        self.available + self.held
+    }
+
+    // the balance after `posting`, none when it would take a book below zero or the total past the largest amount
+    pub fn after(&self, posting: &Posting) -> Option<Balance> {
+        let mut balance = *self;
+        if let Some(amount) = balance.book(posting.from) {
+            *amount = amount.checked_sub(posting.amount)?;
+        }
+        if let Some(amount) = balance.book(posting.to) {
+            *amount = amount.checked_add(posting.amount)?;
+        }
+        balance.available.checked_add(balance.held)?;
+        Some(balance)
+    }
+
+    // for the postings that were checked when they were recorded
+    pub fn apply(&mut self, posting: &Posting) {
+        *self = self.after(posting).expect("a recorded posting keeps the balance in range");
+    }
+
+    fn book(&mut self, book: Book) -> Option<&mut Amount> {
+        match book {
+            Book::Available => Some(&mut self.available),
+            Book::Held => Some(&mut self.held),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Book {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Book::Available => "available",
+            Book::Held => "held",
+            Book::Settlement => "settlement",
+            Book::ChargebackLoss => "chargeback_loss",
+            Book::House => "house",
+            Book::Reversal => "reversal"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for Book {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "available" => Ok(Book::Available),
+            "held" => Ok(Book::Held),
+            "settlement" => Ok(Book::Settlement),
+            "chargeback_loss" => Ok(Book::ChargebackLoss),
+            "house" => Ok(Book::House),
+            "reversal" => Ok(Book::Reversal),
+            _ => Err(format!("unknown book '{}'", name))
+        }
+    }
+}
+
+pub const BOOKS: [Book; 6] = [Book::Available, Book::Held, Book::Settlement, Book::ChargebackLoss, Book::House, Book::Reversal];
+
+// every posting of a client, in application order, with the running balance after it
+pub fn statement<I: IntoIterator<Item=Posting>>(postings: I) -> impl Iterator<Item=(Posting, Balance)> {
+    let mut balance = Balance::default();
+    postings.into_iter().map(move |posting| {
+        balance.apply(&posting);
+        (posting, balance)
+    })
+}
+
+// balance of a client right after the latest posting referencing `tx`
+pub fn balance_at<I: IntoIterator<Item=Posting>>(postings: I, tx: TransactionID) -> Option<Balance> {
+    statement(postings)
+        .filter(|(posting, _)| posting.tx == Some(tx))
+        .last()
+        .map(|(_, balance)| balance)
+}
+
+// the balance of a client book, a balance only fits the signed amounts up to half the largest amount, past that it wraps
+// around. Summing and comparing wrapped balances still tells whether the books add up, and a client book cast back to
+// an amount is exact
+pub fn book_balance<'a, I: IntoIterator<Item=&'a Posting>>(postings: I, book: Book) -> SignedAmount {
+    postings.into_iter().fold(0, |balance: SignedAmount, posting| balance.wrapping_add(posting.delta(book)))
+}
+
+// in-memory journal of the events of every client, the postings are derived from them
+#[derive(Clone)]
+pub struct Ledger {
+    events: HashMap<ClientID, Vec<Event>>
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self {
+            events: HashMap::new()
+        }
+    }
+
+    pub fn record(&mut self, cx: ClientID, event: Event) {
+        self.events.entry(cx).or_default().push(event);
+    }
+
+    pub fn events(&self, cx: ClientID) -> impl Iterator<Item=Event> + '_ {
+        self.events.get(&cx).into_iter().flatten().copied()
+    }
+
+    pub fn postings(&self, cx: ClientID) -> impl Iterator<Item=Posting> + '_ {
+        self.events(cx).filter_map(|event| event.posting())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::events::Event;
+    use crate::ledger::{balance_at, book_balance, statement, Balance, Book, EntryKind, Ledger, Posting};
+
+    #[test]
+    fn posting_kind() {
+        assert_eq!(Posting::new(1, Book::Settlement, Book::Available, 1).kind(), EntryKind::Deposit);
+        assert_eq!(Posting::new(1, Book::Held, Book::ChargebackLoss, 1).kind(), EntryKind::Chargeback);
+        assert_eq!(Posting::new(1, Book::Settlement, Book::Held, 1).kind(), EntryKind::Dispute);
+        assert_eq!(Posting::new(1, Book::House, Book::Held, 1).kind(), EntryKind::Other);
+    }
+
+    #[test]
+    fn book_names() {
+        assert_eq!(Book::ChargebackLoss.to_string(), "chargeback_loss");
+        assert_eq!("chargeback_loss".parse(), Ok(Book::ChargebackLoss));
+        assert!("abc".parse::<Book>().is_err());
+    }
+
+    #[test]
+    fn statement_running_balance() {
+        let mut ledger = Ledger::new();
+        ledger.record(1, Event::Deposited(1, 42));
+        ledger.record(1, Event::DisputeOpened(1, 12));
+        ledger.record(1, Event::Locked(1));
+        let statement: Vec<Balance> = statement(ledger.postings(1)).map(|(_, balance)| balance).collect();
+        assert_eq!(statement, vec![Balance{available: 42, held: 0}, Balance{available: 30, held: 12}]);
+        assert_eq!(statement[1].total(), 42);
+    }
+
+    #[test]
+    fn statement_unknown_client() {
+        let ledger = Ledger::new();
+        assert_eq!(statement(ledger.postings(1)).count(), 0);
+    }
+
+    #[test]
+    fn balance_at_tx() {
+        let mut ledger = Ledger::new();
+        ledger.record(1, Event::Deposited(1, 42));
+        ledger.record(2, Event::Deposited(2, 10));
+        ledger.record(1, Event::Withdrew(3, 2));
+        ledger.record(1, Event::Deposited(4, 8));
+        assert_eq!(balance_at(ledger.postings(1), 3), Some(Balance{available: 40, held: 0}));
+        assert_eq!(balance_at(ledger.postings(1), 2), None);
+    }
+
+    #[test]
+    fn balance_at_latest_posting() {
+        let mut ledger = Ledger::new();
+        ledger.record(1, Event::Deposited(1, 42));
+        ledger.record(1, Event::Deposited(2, 8));
+        ledger.record(1, Event::DisputeOpened(1, 42));
+        assert_eq!(balance_at(ledger.postings(1), 1), Some(Balance{available: 8, held: 42}));
+    }
+
+    #[test]
+    fn book_balances() {
+        let postings = vec![
+            Posting::new(1, Book::Settlement, Book::Available, 42),
+            Posting::new(1, Book::Available, Book::Held, 42),
+            Posting::new(1, Book::Held, Book::ChargebackLoss, 42)
+        ];
+        assert_eq!(book_balance(&postings, Book::Available), 0);
+        assert_eq!(book_balance(&postings, Book::Settlement), -42);
+        assert_eq!(book_balance(&postings, Book::ChargebackLoss), 42);
+    }
+}
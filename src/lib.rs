@@ -0,0 +1,24 @@
+pub mod common_types;
+pub mod scale;
+pub mod transaction_engine;
+pub mod transaction_store;
+pub mod storage;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_storage;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_export;
+pub mod account;
+pub mod parser;
+pub mod lint;
+pub mod output;
+pub mod account_diff;
+pub mod ledger;
+pub mod events;
+pub mod verify;
+pub mod generator;
+pub mod config;
+pub mod rules;
+pub mod heuristics;
+pub mod limits;
+pub mod fees;
+pub mod disputes;
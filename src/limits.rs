@@ -0,0 +1,207 @@
+// Per-client limits enforced on deposits and withdrawals. Every client gets the default limits unless the limits CSV
+// overrides some of them:
+// client(u16), max_balance(decimal), max_deposit(decimal), max_withdrawals(u32), window_rows(u64), window_seconds(u64)
+// an empty cell keeps the default, the amounts are refused as input amounts with more decimals than the scale are
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use serde::Deserialize;
+use crate::common_types::*;
+use crate::scale::{AmountSetting, Scale};
+
+// the rolling window withdrawals are counted over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    Rows(u64),
+    Seconds(u64)
+}
+
+impl Window {
+    // a window is counted either in rows or in seconds, never both
+    pub fn from_settings(rows: Option<u64>, seconds: Option<u64>) -> Result<Option<Window>, String> {
+        match (rows, seconds) {
+            (Some(_), Some(_)) => Err("window_rows and window_seconds cannot both be set".to_string()),
+            (rows, seconds) => Ok(rows.map(Window::Rows).or(seconds.map(Window::Seconds)))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub max_balance: Option<Amount>,
+    pub max_deposit: Option<Amount>,
+    // withdrawals allowed per window, or over the whole run without a window
+    pub max_withdrawals: Option<u32>,
+    pub window: Option<Window>
+}
+
+impl Limits {
+    // the limits set here, completed by `defaults`
+    pub fn or(self, defaults: Limits) -> Limits {
+        Limits {
+            max_balance: self.max_balance.or(defaults.max_balance),
+            max_deposit: self.max_deposit.or(defaults.max_deposit),
+            max_withdrawals: self.max_withdrawals.or(defaults.max_withdrawals),
+            window: self.window.or(defaults.window)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Row {
+    client: ClientID,
+    max_balance: Option<AmountSetting>,
+    max_deposit: Option<AmountSetting>,
+    max_withdrawals: Option<u32>,
+    window_rows: Option<u64>,
+    window_seconds: Option<u64>
+}
+
+pub fn parse_limits_csv<P: AsRef<std::path::Path>>(path: P, scale: Scale) -> Result<HashMap<ClientID, Limits>, Box<dyn Error>> {
+    parse_limits_reader(std::fs::File::open(path)?, scale)
+}
+
+pub fn parse_limits_reader<R: std::io::Read>(input: R, scale: Scale) -> Result<HashMap<ClientID, Limits>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+    let mut overrides = HashMap::new();
+    for row in reader.deserialize() {
+        let row: Row = row?;
+        let amount = |amount: Option<AmountSetting>| amount.map(|amount| amount.to_amount(scale)).transpose()
+            .map_err(|e| format!("client {}: {}", row.client, e));
+        overrides.insert(row.client, Limits {
+            max_balance: amount(row.max_balance)?,
+            max_deposit: amount(row.max_deposit)?,
+            max_withdrawals: row.max_withdrawals,
+            window: Window::from_settings(row.window_rows, row.window_seconds).map_err(|e| format!("client {}: {}", row.client, e))?
+        });
+    }
+    Ok(overrides)
+}
+
+// the limits of every client and the recent withdrawals they are checked against
+#[derive(Clone)]
+pub struct ClientLimits {
+    defaults: Limits,
+    overrides: HashMap<ClientID, Limits>,
+    // input row and time in seconds of the withdrawals still in the window, per client
+    withdrawals: HashMap<ClientID, VecDeque<(u64, u64)>>
+}
+
+impl ClientLimits {
+    pub fn new(defaults: Limits, overrides: HashMap<ClientID, Limits>) -> Self {
+        Self {
+            defaults,
+            overrides,
+            withdrawals: HashMap::new()
+        }
+    }
+
+    pub fn limits(&self, cx: ClientID) -> Limits {
+        self.overrides.get(&cx).map_or(self.defaults, |limits| limits.or(self.defaults))
+    }
+
+    // `total` is the client balance before the deposit
+    pub fn check_deposit(&self, cx: ClientID, total: Amount, amount: Amount) -> Result<(), RejectReason> {
+        let limits = self.limits(cx);
+        if limits.max_deposit.is_some_and(|max_deposit| amount > max_deposit) {
+            return Err(RejectReason::LimitExceeded("max_deposit"));
+        }
+        if limits.max_balance.is_some_and(|max_balance| total.saturating_add(amount) > max_balance) {
+            return Err(RejectReason::LimitExceeded("max_balance"));
+        }
+        Ok(())
+    }
+
+    // `position` is the input row of the withdrawal and `now` the current time in seconds
+    pub fn check_withdrawal(&mut self, cx: ClientID, position: u64, now: u64) -> Result<(), RejectReason> {
+        let limits = self.limits(cx);
+        if let Some(max_withdrawals) = limits.max_withdrawals {
+            let withdrawals = self.withdrawals.entry(cx).or_default();
+            // forget the withdrawals that left the window
+            while let Some((row, seconds)) = withdrawals.front() {
+                let expired = match limits.window {
+                    Some(Window::Rows(rows)) => position.saturating_sub(*row) > rows,
+                    // the clock may go back, a withdrawal recorded in the future stays in the window
+                    Some(Window::Seconds(window)) => now.saturating_sub(*seconds) > window,
+                    None => false
+                };
+                if !expired {
+                    break;
+                }
+                withdrawals.pop_front();
+            }
+            if withdrawals.len() >= max_withdrawals as usize {
+                return Err(RejectReason::LimitExceeded("max_withdrawals"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record_withdrawal(&mut self, cx: ClientID, position: u64, now: u64) {
+        if self.limits(cx).max_withdrawals.is_some() {
+            self.withdrawals.entry(cx).or_default().push_back((position, now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::common_types::RejectReason;
+    use crate::limits::{parse_limits_reader, ClientLimits, Limits, Window};
+    use crate::scale::Scale;
+
+    #[test]
+    fn parse_limits() {
+        let input = "client,max_balance,max_deposit,max_withdrawals,window_rows,window_seconds
+            1,100.5,,2,10,
+            2,,1.0,,,60";
+        let overrides = parse_limits_reader(input.as_bytes(), Scale::default()).unwrap();
+        assert_eq!(overrides[&1], Limits{max_balance: Some(1005000), max_deposit: None, max_withdrawals: Some(2), window: Some(Window::Rows(10))});
+        assert_eq!(overrides[&2], Limits{max_balance: None, max_deposit: Some(10000), max_withdrawals: None, window: Some(Window::Seconds(60))});
+        let input = "client,max_balance,max_deposit,max_withdrawals,window_rows,window_seconds
+            1,,,2,10,60";
+        assert!(parse_limits_reader(input.as_bytes(), Scale::default()).is_err());
+        for amount in ["1e3", "0.00001", "-1"] {
+            let input = format!("client,max_balance,max_deposit,max_withdrawals,window_rows,window_seconds\n1,{},,,,", amount);
+            assert!(parse_limits_reader(input.as_bytes(), Scale::default()).is_err());
+        }
+    }
+
+    #[test]
+    fn overrides() {
+        let defaults = Limits{max_balance: Some(100), max_deposit: Some(10), ..Default::default()};
+        let limits = ClientLimits::new(defaults, HashMap::from([(1, Limits{max_deposit: Some(50), ..Default::default()})]));
+        assert_eq!(limits.limits(1), Limits{max_balance: Some(100), max_deposit: Some(50), ..Default::default()});
+        assert_eq!(limits.limits(2), defaults);
+        assert_eq!(limits.check_deposit(1, 0, 50), Ok(()));
+        assert_eq!(limits.check_deposit(2, 0, 50), Err(RejectReason::LimitExceeded("max_deposit")));
+        assert_eq!(limits.check_deposit(1, 60, 50), Err(RejectReason::LimitExceeded("max_balance")));
+    }
+
+    #[test]
+    fn withdrawals_rows_window() {
+        let defaults = Limits{max_withdrawals: Some(2), window: Some(Window::Rows(3)), ..Default::default()};
+        let mut limits = ClientLimits::new(defaults, HashMap::new());
+        for position in 1..=2 {
+            assert_eq!(limits.check_withdrawal(1, position, 0), Ok(()));
+            limits.record_withdrawal(1, position, 0);
+        }
+        assert_eq!(limits.check_withdrawal(1, 3, 0), Err(RejectReason::LimitExceeded("max_withdrawals")));
+        assert_eq!(limits.check_withdrawal(2, 3, 0), Ok(()));
+        // the first withdrawal left the window
+        assert_eq!(limits.check_withdrawal(1, 5, 0), Ok(()));
+    }
+
+    #[test]
+    fn withdrawals_seconds_window() {
+        let defaults = Limits{max_withdrawals: Some(1), window: Some(Window::Seconds(60)), ..Default::default()};
+        let mut limits = ClientLimits::new(defaults, HashMap::new());
+        limits.record_withdrawal(1, 1, 1000);
+        assert_eq!(limits.check_withdrawal(1, 2, 1060), Err(RejectReason::LimitExceeded("max_withdrawals")));
+        assert_eq!(limits.check_withdrawal(1, 3, 1061), Ok(()));
+        // the clock went back
+        limits.record_withdrawal(1, 4, 2000);
+        assert_eq!(limits.check_withdrawal(1, 5, 1900), Err(RejectReason::LimitExceeded("max_withdrawals")));
+    }
+}
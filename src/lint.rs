@@ -0,0 +1,166 @@
+// Checks an input file without applying it: every row that cannot be processed or is misread, with its line number, and
+// the rows that parse but that an engine starting from an empty state refuses.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::common_types::*;
+use crate::config::{Config, NegativeAmounts};
+use crate::parser;
+use crate::transaction_engine::TransactionEngine;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IssueKind {
+    InvalidRow,
+    // a partial dispute, resolve or chargeback
+    DisputeAmount,
+    ExcessivePrecision,
+    NegativeAmount,
+    // refused by the engine for the given reason
+    Rejected(RejectReason)
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IssueKind::InvalidRow => write!(f, "invalid_row"),
+            IssueKind::DisputeAmount => write!(f, "dispute_amount"),
+            IssueKind::ExcessivePrecision => write!(f, "excessive_precision"),
+            IssueKind::NegativeAmount => write!(f, "negative_amount"),
+            IssueKind::Rejected(reason) => write!(f, "{}", reason)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub line: u64,
+    pub kind: IssueKind,
+    pub message: String
+}
+
+pub fn lint_csv<P: AsRef<std::path::Path>>(path: P, config: &Config) -> std::io::Result<Vec<Issue>> {
+    Ok(lint_reader(std::fs::File::open(path)?, config)?)
+}
+
+// every issue of the input in line order, as read and processed with `config`. Headers that cannot be read are an error
+// rather than an issue, no row can be checked without them
+pub fn lint_reader<R: std::io::Read>(input: R, config: &Config) -> csv::Result<Vec<Issue>> {
+    let mut reader = parser::csv_reader(input, &config.input);
+    let columns = parser::Columns::read(&mut reader, &config.input)?;
+    let amount_column = columns.position("amount");
+    // the rows are applied to a throwaway engine, so the checks are the ones `process` makes
+    let mut engine = TransactionEngine::new();
+    engine.set_dispute_withdrawals(config.disputes.withdrawals);
+    let mut issues = Vec::new();
+    for record in reader.into_records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                let line = e.position().map_or(0, |position| position.line());
+                issues.push(Issue{line, kind: IssueKind::InvalidRow, message: e.to_string()});
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |position| position.line());
+        let mut issue = |kind, message| issues.push(Issue{line, kind, message});
+        if let Some(amount) = amount_column.and_then(|column| record.get(column)).map(str::trim).filter(|amount| !amount.is_empty()) {
+            if amount.starts_with('-') {
+                if config.input.negative_amounts == NegativeAmounts::Reject {
+                    issue(IssueKind::NegativeAmount, format!("negative amount {} is refused", amount));
+                    continue;
+                }
+                issue(IssueKind::NegativeAmount, format!("negative amount {} is read as 0", amount));
+            } else if amount.split_once('.').map_or(0, |(_, fraction)| fraction.len()) > config.decimals.decimals() as usize {
+                // the parser refuses the row, no need to report it twice
+                issue(IssueKind::ExcessivePrecision, format!("amount {} has more than {} decimals and is refused", amount, config.decimals.decimals()));
+                continue;
+            }
+        }
+        let transaction = match parser::parse_record(&record, &columns, config) {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                issue(IssueKind::InvalidRow, e);
+                continue;
+            }
+        };
+        if let Some(amount) = transaction.amount().filter(|_| matches!(transaction, Transaction::Dispute(..) | Transaction::Resolve(..) | Transaction::Chargeback(..))) {
+            issue(IssueKind::DisputeAmount, format!("{} of {} only, not the whole deposit", transaction.name(), config.decimals.format(amount)));
+        }
+        engine.process_line(line, transaction);
+    }
+    // the in-memory storage cannot fail
+    issues.extend(engine.get_rejections().flatten().map(|rejection| Issue {
+        line: rejection.line,
+        message: format!("{} of tx {} by client {} is refused", rejection.transaction.name(), rejection.transaction.tx(), rejection.transaction.client()),
+        kind: IssueKind::Rejected(rejection.reason)
+    }));
+    // stable, the issues of a row found while parsing it come first
+    issues.sort_by_key(|issue| issue.line);
+    Ok(issues)
+}
+
+// how many issues of each kind were found
+pub fn summary(issues: &[Issue]) -> BTreeMap<IssueKind, usize> {
+    let mut counts = BTreeMap::new();
+    for issue in issues {
+        *counts.entry(issue.kind.clone()).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common_types::RejectReason;
+    use crate::config::Config;
+    use crate::lint::{lint_reader, summary, IssueKind};
+
+    #[test]
+    fn issues() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.5\n\
+                     withdrawal,1,2,0.5\n\
+                     deposit,2,1,3\n\
+                     dispute,1,3,\n\
+                     dispute,1,2,\n\
+                     dispute,2,1,\n\
+                     dispute,1,1,0.5\n\
+                     deposit,1,4,0.00001\n\
+                     deposit,1,5,-1\n\
+                     transfer,1,6,1\n\
+                     reversal,1,2,\n\
+                     withdrawal,1,7,5\n\
+                     resolve,1,1,0.6\n";
+        let issues: Vec<(u64, IssueKind)> = lint_reader(input.as_bytes(), &Config::default()).unwrap().into_iter().map(|issue| (issue.line, issue.kind)).collect();
+        assert_eq!(issues, vec![
+            (4, IssueKind::Rejected(RejectReason::DuplicateTransaction)),
+            (5, IssueKind::Rejected(RejectReason::UnknownTransaction)),
+            (6, IssueKind::Rejected(RejectReason::NotADeposit)),
+            (7, IssueKind::Rejected(RejectReason::WrongClient)),
+            (8, IssueKind::DisputeAmount),
+            (9, IssueKind::ExcessivePrecision),
+            (10, IssueKind::NegativeAmount),
+            (11, IssueKind::InvalidRow),
+            // found by the engine only
+            (13, IssueKind::Rejected(RejectReason::InsufficientFunds)),
+            (14, IssueKind::DisputeAmount),
+            (14, IssueKind::Rejected(RejectReason::ExceedsDispute))
+        ]);
+        let issues = lint_reader(input.as_bytes(), &Config::default()).unwrap();
+        let counts = summary(&issues);
+        assert_eq!(counts.len(), 10);
+        assert_eq!(counts[&IssueKind::DisputeAmount], 2);
+
+        let config = Config::parse("[input]\nnegative_amounts = \"reject\"\n[disputes]\nwithdrawals = true\n").unwrap();
+        let issues: Vec<(u64, IssueKind)> = lint_reader(input.as_bytes(), &config).unwrap().into_iter().map(|issue| (issue.line, issue.kind)).collect();
+        assert!(!issues.contains(&(6, IssueKind::Rejected(RejectReason::NotADeposit))));
+        assert_eq!(issues.iter().filter(|(line, _)| *line == 10).count(), 1);
+
+        // the amount column is found through the mapping
+        let config = Config::parse("[input]\ndelimiter = \";\"\n[input.mapping]\nid = \"tx\"\nvalue = \"amount\"\n").unwrap();
+        let input = "type;client;id;value;batch\ndeposit;1;1;1;a\ndeposit;1;1;1;b\ndeposit;1;2;1.00001;c\n";
+        let issues: Vec<(u64, IssueKind)> = lint_reader(input.as_bytes(), &config).unwrap().into_iter().map(|issue| (issue.line, issue.kind)).collect();
+        assert_eq!(issues, vec![(3, IssueKind::Rejected(RejectReason::DuplicateTransaction)), (4, IssueKind::ExcessivePrecision)]);
+
+        assert!(lint_reader(&b"type,client,tx,\xffamount\n"[..], &Config::default()).is_err());
+    }
+}
@@ -1,40 +1,555 @@
-use clap::Parser;
-use log::error;
-
-mod common_types;
-mod transaction_engine;
-mod account;
-mod parser;
-mod output;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use clap::{Args, Parser, Subcommand};
+use log::{error, warn};
+use transaction_engine::common_types::{ClientID, TransactionID};
+use transaction_engine::transaction_engine::{RetentionPolicy, TransactionEngine};
+use transaction_engine::transaction_store::TransactionStore;
+use transaction_engine::storage::{MemoryStorage, Storage, StorageResult};
+use transaction_engine::rules::{RiskRules, RulesConfig};
+use transaction_engine::config::{Config, InterestConfig, LimitsConfig, RetentionConfig};
+use transaction_engine::limits::{self, ClientLimits, Limits, Window};
+use transaction_engine::fees::{FeeSchedule, InterestPolicy, Rate};
+use transaction_engine::scale::{AmountSetting, Scale};
+use transaction_engine::account_diff::{self, Decimal};
+use transaction_engine::{lint, output, parser, verify};
+use transaction_engine::output::RejectedRow;
 
 #[derive(Parser)]
-#[clap(author, version, about, long_about=None)]
-struct Args {
-    /// Path to input file
-    #[clap(value_parser)]
-    path: String,
+#[clap(author, version, about, long_about=None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    // without a subcommand the input is processed as with `process`
+    #[clap(flatten)]
+    process: ProcessArgs,
 
     /// Verbose mode (-v, -vv, -vvv)
-    #[clap(short, long, action = clap::ArgAction::Count)]
-    verbose: u8
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// TOML config file, the command line options take precedence over it
+    #[clap(long, global = true, value_parser)]
+    config: Option<String>,
+
+    /// Decimals of the fixed point amounts read and written (4 by default)
+    #[clap(long, global = true, value_parser = parse_scale)]
+    decimals: Option<Scale>,
+
+    /// Print the effective config, the config file with the command line options applied, and exit
+    #[clap(long, global = true)]
+    print_config: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>
 }
 
-fn main() {
-    let args = Args::parse();
+#[derive(Subcommand)]
+enum Command {
+    /// Process an input file and output the final accounts, or one of the reports selected by its options
+    Process(ProcessArgs),
+
+    /// Check an input file without applying it and report every issue with its line number and a count per issue
+    Validate {
+        /// Path to input file
+        #[clap(value_parser)]
+        path: String
+    },
+
+    /// Compare two account outputs and report the added, removed and changed clients
+    Diff {
+        /// Account output of the earlier run
+        #[clap(value_parser)]
+        before: String,
+
+        /// Account output of the later run
+        #[clap(value_parser)]
+        after: String,
+
+        /// Exit with status 1 when an amount changed by more than this, or a client was added, removed, locked or unlocked
+        #[clap(long, value_parser = Decimal::parse_tolerance, default_value = "0")]
+        tolerance: Decimal
+    },
+
+    /// Process an input file and output the final account of a single client followed by its event history
+    InspectClient {
+        /// Client to inspect
+        #[clap(value_parser)]
+        client: ClientID,
+
+        #[clap(flatten)]
+        engine: EngineArgs
+    }
+}
+
+#[derive(Args)]
+struct ProcessArgs {
+    #[clap(flatten)]
+    engine: EngineArgs,
+
+    /// Output the statement of a single client instead of the final accounts
+    #[clap(long, value_parser)]
+    statement: Option<ClientID>,
+
+    /// With --statement, output the client balance as of the given transaction
+    #[clap(long, value_parser, requires = "statement")]
+    as_of: Option<TransactionID>,
+
+    /// Output the event stream of a single client, with its account after each event, instead of the final accounts
+    #[clap(long, value_parser, conflicts_with = "statement")]
+    events: Option<ClientID>,
+
+    /// Output every dispute case, open, resolved or charged back, instead of the final accounts
+    #[clap(long, conflicts_with_all = &["statement", "events"])]
+    dispute_cases: bool,
+
+    /// Output every deposit still under dispute with the amount held and the input line the dispute was opened at
+    #[clap(long, conflicts_with_all = &["statement", "events", "dispute-cases"])]
+    open_disputes: bool,
+
+    /// Output every transaction the engine refused with its input line, reason and the columns of its row the parser passed through
+    #[clap(long, conflicts_with_all = &["statement", "events", "dispute-cases", "open-disputes"])]
+    rejections: bool,
+
+    /// Apply the transactions of that file to a copy of the state built from the input and output every account they would change
+    #[clap(long, value_parser, conflicts_with_all = &["statement", "events", "dispute-cases", "open-disputes", "rejections"])]
+    what_if: Option<String>,
+
+    /// Recompute every client from the ledger and stored transactions after processing and report discrepancies
+    #[clap(long)]
+    verify: bool,
+
+    /// Also write the final accounts, transaction history and rejections to a SQLite database
+    #[cfg(feature = "sqlite")]
+    #[clap(long, value_parser)]
+    sqlite: Option<String>,
+}
+
+// the input and everything that configures the engine processing it
+#[derive(Args)]
+struct EngineArgs {
+    /// Path to input file
+    #[clap(value_parser, required_unless_present = "print-config")]
+    path: Option<String>,
+
+    /// Keep the engine state in a SQLite database instead of memory, resuming from it if it exists
+    #[cfg(feature = "sqlite")]
+    #[clap(long, value_parser)]
+    #[cfg_attr(feature = "spill", clap(conflicts_with = "memory-budget"))]
+    storage: Option<String>,
+
+    /// Memory budget in MiB for the stored transactions, colder transactions are spilled to disk above it
+    #[cfg(feature = "spill")]
+    #[clap(long, value_parser)]
+    memory_budget: Option<usize>,
+
+    /// Directory of the spill file used with --memory-budget
+    #[cfg(feature = "spill")]
+    #[clap(long, value_parser, requires = "memory-budget")]
+    spill_dir: Option<String>,
+
+    /// Deposits can only be disputed for that many rows, they are evicted afterwards
+    #[clap(long, value_parser, conflicts_with = "retention-seconds")]
+    retention_rows: Option<u64>,
+
+    /// Deposits can only be disputed for that many seconds, they are evicted afterwards
+    #[clap(long, value_parser)]
+    retention_seconds: Option<u64>,
+
+    /// Evicts the deposits past their dispute window every that many rows
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    prune_interval: Option<u64>,
+
+    /// Risk rules checked before applying each transaction (TOML), the file is reloaded when it changes
+    #[clap(long, value_parser)]
+    rules: Option<String>,
+
+    /// Default maximum total balance of a client
+    #[clap(long, value_parser)]
+    max_balance: Option<AmountSetting>,
+
+    /// Default maximum amount of a single deposit
+    #[clap(long, value_parser)]
+    max_deposit: Option<AmountSetting>,
+
+    /// Default maximum number of withdrawals of a client per window, over the whole input without a window
+    #[clap(long, value_parser)]
+    max_withdrawals: Option<u32>,
+
+    /// Count the withdrawals of --max-withdrawals over that many rows
+    #[clap(long, value_parser, conflicts_with = "withdrawal-window-seconds")]
+    withdrawal_window_rows: Option<u64>,
+
+    /// Count the withdrawals of --max-withdrawals over that many seconds
+    #[clap(long, value_parser)]
+    withdrawal_window_seconds: Option<u64>,
+
+    /// CSV of per-client limits overriding the defaults (client,max_balance,max_deposit,max_withdrawals,window_rows,window_seconds)
+    #[clap(long, value_parser)]
+    limits: Option<String>,
 
-    stderrlog::new().module(module_path!()).verbosity(args.verbose as usize).init().unwrap();
+    /// Flat fee charged on every withdrawal
+    #[clap(long, value_parser)]
+    withdrawal_fee: Option<AmountSetting>,
 
-    let mut transaction_engine = transaction_engine::TransactionEngine::new();
+    /// Fee charged on every withdrawal as a fraction of its amount, e.g. 0.01
+    #[clap(long, value_parser)]
+    withdrawal_fee_rate: Option<Rate>,
 
-    if let Ok(transactions) = parser::parse_csv(args.path) {
-        for transaction in transactions {
-            transaction_engine.process_transaction(transaction);
+    /// Penalty fee charged after a chargeback, as much as the available funds cover
+    #[clap(long, value_parser)]
+    chargeback_fee: Option<AmountSetting>,
+
+    /// Interest paid on the available funds of unlocked accounts every --interest-period rows, e.g. 0.001
+    #[clap(long, value_parser, requires = "interest-period")]
+    interest_rate: Option<Rate>,
+
+    /// Number of rows between two interest payments
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), requires = "interest-rate")]
+    interest_period: Option<u64>,
+}
+
+// exits with 1 when a difference exceeds the tolerance and 2 when an output cannot be read
+fn diff(before: &str, after: &str, tolerance: Decimal) -> i32 {
+    let (before, after) = match (account_diff::parse_accounts_csv(before), account_diff::parse_accounts_csv(after)) {
+        (Ok(before), Ok(after)) => (before, after),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to parse the account output: {}", e);
+            return 2;
         }
+    };
+    let diffs = account_diff::diff_accounts(&before, &after);
+    if let Err(e) = output::output_account_diff(&mut std::io::stdout().lock(), diffs.iter()) {
+        error!("Failed to write the output: {}", e);
+    }
+    i32::from(diffs.iter().any(|diff| diff.exceeds(tolerance)))
+}
 
-        output::output_accounts(transaction_engine.get_accounts());
-    } else {
-        error!("Failed to parse the input file");
-        return;
+// exits with 1 when the input has an issue
+fn validate(path: &str, config: &Config) -> i32 {
+    let issues = match lint::lint_csv(path, config) {
+        Ok(issues) => issues,
+        Err(e) => {
+            error!("Failed to read the input file: {}", e);
+            return 2;
+        }
+    };
+    if let Err(e) = output::output_lint_report(&mut std::io::stdout().lock(), &issues) {
+        error!("Failed to write the output: {}", e);
+    }
+    i32::from(!issues.is_empty())
+}
+
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+fn create_storage(args: &EngineArgs, scale: Scale) -> Result<Box<dyn Storage>, String> {
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.storage {
+        return transaction_engine::sqlite_storage::SqliteStorage::open(path, scale)
+            .map(|storage| Box::new(storage) as Box<dyn Storage>)
+            .map_err(|e| format!("Failed to open the SQLite storage: {}", e));
+    }
+    #[cfg(feature = "spill")]
+    if let Some(memory_budget) = args.memory_budget {
+        let spill_dir = args.spill_dir.clone().map(std::path::PathBuf::from).unwrap_or_else(std::env::temp_dir);
+        let transaction_store = TransactionStore::with_spill(memory_budget * 1024 * 1024, spill_dir)
+            .map_err(|e| format!("Failed to create the transaction spill file: {}", e))?;
+        return Ok(Box::new(MemoryStorage::with_transaction_store(transaction_store)));
     }
+    let transaction_store = TransactionStore::new();
+    Ok(Box::new(MemoryStorage::with_transaction_store(transaction_store)))
 }
 
+fn parse_scale(decimals: &str) -> Result<Scale, String> {
+    Scale::new(decimals.parse().map_err(|e: std::num::ParseIntError| e.to_string())?)
+}
+
+fn create_limits(config: &LimitsConfig, scale: Scale) -> Result<Option<ClientLimits>, String> {
+    let amount = |amount: &Option<AmountSetting>| amount.as_ref().map(|amount| amount.to_amount(scale)).transpose()
+        .map_err(|e| format!("Invalid limits: {}", e));
+    let defaults = Limits {
+        max_balance: amount(&config.max_balance)?,
+        max_deposit: amount(&config.max_deposit)?,
+        max_withdrawals: config.max_withdrawals,
+        window: Window::from_settings(config.window_rows, config.window_seconds).map_err(|e| format!("Invalid limits: {}", e))?
+    };
+    let overrides = match &config.file {
+        Some(path) => limits::parse_limits_csv(path, scale).map_err(|e| format!("Failed to parse the limits file: {}", e))?,
+        None => HashMap::new()
+    };
+    if defaults == Limits::default() && overrides.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(ClientLimits::new(defaults, overrides)))
+}
+
+// the command line options take precedence over the config file
+fn apply_overrides(config: &mut Config, args: &EngineArgs) -> Result<(), String> {
+    if args.retention_rows.is_some() || args.retention_seconds.is_some() {
+        config.retention = RetentionConfig{rows: args.retention_rows, seconds: args.retention_seconds, ..config.retention};
+    }
+    config.retention.prune_interval = args.prune_interval.or(config.retention.prune_interval);
+    let limits = &mut config.limits;
+    limits.max_balance = args.max_balance.clone().or(limits.max_balance.take());
+    limits.max_deposit = args.max_deposit.clone().or(limits.max_deposit.take());
+    limits.max_withdrawals = args.max_withdrawals.or(limits.max_withdrawals);
+    if args.withdrawal_window_rows.is_some() || args.withdrawal_window_seconds.is_some() {
+        limits.window_rows = args.withdrawal_window_rows;
+        limits.window_seconds = args.withdrawal_window_seconds;
+    }
+    limits.file = args.limits.clone().or(limits.file.take());
+    let fees = &mut config.fees;
+    fees.withdrawal = args.withdrawal_fee.clone().or(fees.withdrawal.take());
+    fees.withdrawal_rate = args.withdrawal_fee_rate.or(fees.withdrawal_rate);
+    fees.chargeback = args.chargeback_fee.clone().or(fees.chargeback.take());
+    if let Some((rate, period_rows)) = args.interest_rate.zip(args.interest_period) {
+        config.interest = Some(InterestConfig{rate, period_rows});
+    }
+    if let Some(path) = &args.rules {
+        config.rules = Some(RulesConfig::load(path).map_err(|e| format!("Failed to load the risk rules: {}", e))?);
+    }
+    Ok(())
+}
+
+// the engine after processing the input, and what the reports need from the input itself
+struct Run {
+    engine: TransactionEngine,
+    // whether fraud heuristics are enabled
+    has_heuristics: bool,
+}
+
+// builds the engine the config describes and processes the input. The rows refused in this run are written to `report`
+// as they are processed, a storage resumed from an earlier run also holds the rejections of that run
+fn run(config: &Config, args: &EngineArgs, mut report: Option<&mut dyn Write>) -> Result<Run, String> {
+    let amount = |amount: &Option<AmountSetting>| amount.as_ref().map_or(Ok(0), |amount| amount.to_amount(config.decimals))
+        .map_err(|e| format!("Invalid fees: {}", e));
+    let mut transaction_engine = TransactionEngine::with_storage(create_storage(args, config.decimals)?)
+        .map_err(|e| format!("Failed to read the storage: {}", e))?;
+    transaction_engine.set_retention_policy(config.retention.rows.map(RetentionPolicy::Rows)
+        .or(config.retention.seconds.map(RetentionPolicy::Seconds)));
+    if let Some(prune_interval) = config.retention.prune_interval {
+        transaction_engine.set_prune_interval(prune_interval);
+    }
+    transaction_engine.set_fees(FeeSchedule {
+        withdrawal_flat: amount(&config.fees.withdrawal)?,
+        withdrawal_rate: config.fees.withdrawal_rate,
+        chargeback: amount(&config.fees.chargeback)?
+    });
+    transaction_engine.set_interest(config.interest.as_ref().map(|interest| InterestPolicy{rate: interest.rate, period_rows: interest.period_rows}));
+    transaction_engine.set_limits(create_limits(&config.limits, config.decimals)?);
+    transaction_engine.set_dispute_withdrawals(config.disputes.withdrawals);
+    // a rules file given on the command line is reloaded when it changes
+    let rules = match &args.rules {
+        Some(path) => Some(RiskRules::from_file(path, config.decimals)),
+        None => config.rules.clone().map(|rules| RiskRules::new(rules, config.decimals))
+    }.transpose().map_err(|e| format!("Failed to load the risk rules: {}", e))?;
+    let has_heuristics = rules.as_ref().is_some_and(|rules| rules.config().has_heuristics());
+    transaction_engine.set_rules(rules);
+
+    let input = std::fs::File::open(args.path.as_deref().unwrap_or_default())
+        .map_err(|e| format!("Failed to parse the input file: {}", e))?;
+    let (extra_columns, rows) = parser::parse_rows(input, config).map_err(|e| format!("Failed to read the input headers: {}", e))?;
+    let write_error = |e: io::Error| format!("Failed to write the output: {}", e);
+    if let Some(report) = &mut report {
+        output::output_rejections_header(report, &extra_columns).map_err(write_error)?;
+    }
+    for (line, transaction, extra) in rows {
+        let transaction = match transaction {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                error!("Skipping line {}: {}", line, e);
+                if let Some(report) = &mut report {
+                    output::output_rejection(report, config.decimals, RejectedRow::Unparsable(line, &e), &extra).map_err(write_error)?;
+                }
+                continue;
+            }
+        };
+        transaction_engine.process_line(line, transaction);
+        if let Some(e) = transaction_engine.failure() {
+            return Err(format!("Storage failure at line {}: {}", line, e));
+        }
+        if let (Some(report), Some(rejection)) = (&mut report, transaction_engine.last_rejection()) {
+            output::output_rejection(report, config.decimals, RejectedRow::Rejected(rejection), &extra).map_err(write_error)?;
+        }
+    }
+
+    transaction_engine.flush().map_err(|e| format!("Failed to flush the storage: {}", e))?;
+
+    if !transaction_engine.is_balanced().map_err(|e| format!("Failed to check the ledger: {}", e))? {
+        error!("Ledger invariant violated, account balances do not match their postings");
+    }
+    Ok(Run{engine: transaction_engine, has_heuristics})
+}
+
+// exits with 1 when the input cannot be processed, an output cannot be written or the verification fails
+fn process(config: &Config, args: &ProcessArgs) -> i32 {
+    let mut stdout = std::io::stdout().lock();
+    let report = args.rejections.then_some(&mut stdout as &mut dyn Write);
+    let Run{engine: transaction_engine, has_heuristics} = match run(config, &args.engine, report) {
+        Ok(run) => run,
+        Err(e) => {
+            error!("{}", e);
+            return 1;
+        }
+    };
+
+    let changes = match &args.what_if {
+        Some(path) => {
+            let candidate = match parser::parse_csv_with(path, config) {
+                Ok(candidate) => candidate,
+                Err(e) => {
+                    error!("Failed to parse the what-if file: {}", e);
+                    return 1;
+                }
+            };
+            match transaction_engine.what_if(candidate) {
+                Ok(Some(changes)) => Some(changes),
+                Ok(None) => {
+                    error!("The what-if simulation needs the in-memory storage");
+                    return 1;
+                },
+                Err(e) => {
+                    error!("Storage failure during the what-if simulation: {}", e);
+                    return 1;
+                }
+            }
+        },
+        None => None
+    };
+
+    let result = match (args.statement, args.as_of, args.events) {
+        _ if changes.is_some() => output::output_account_changes(&mut stdout, config.decimals, changes.iter().flatten()),
+        (Some(cx), Some(tx), _) => match transaction_engine.get_balance_at(cx, tx) {
+            Ok(Some(balance)) => output::output_balance(&mut stdout, config.decimals, cx, balance),
+            Ok(None) => {
+                error!("No transaction {} applied to client {}", tx, cx);
+                Ok(())
+            },
+            Err(e) => Err(e.into())
+        },
+        (Some(cx), None, _) => transaction_engine.get_statement(cx).map_err(io::Error::from)
+            .and_then(|statement| output::output_statement(&mut stdout, config.decimals, statement)),
+        (_, _, Some(cx)) => transaction_engine.get_history(cx).map_err(io::Error::from)
+            .and_then(|history| output::output_events(&mut stdout, config.decimals, history)),
+        _ if args.open_disputes => transaction_engine.get_open_disputes().map_err(io::Error::from)
+            .and_then(|disputes| output::output_open_disputes(&mut stdout, config.decimals, disputes)),
+        // written while the input was processed
+        _ if args.rejections => Ok(()),
+        _ if args.dispute_cases => read_all(transaction_engine.get_dispute_cases())
+            .and_then(|cases| output::output_dispute_cases(&mut stdout, config.decimals, cases.into_iter())),
+        _ if has_heuristics => read_all(transaction_engine.get_accounts())
+            .and_then(|accounts| output::output_flagged_accounts(&mut stdout, config.decimals, accounts.into_iter())),
+        _ => read_all(transaction_engine.get_accounts())
+            .and_then(|accounts| output::output_accounts(&mut stdout, config.decimals, accounts.into_iter()))
+    };
+    let mut status = 0;
+    if let Err(e) = result {
+        error!("Failed to write the output: {}", e);
+        status = 1;
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.sqlite {
+        if let Err(e) = transaction_engine::sqlite_export::export_sqlite(&transaction_engine, path) {
+            error!("Failed to export to the SQLite database: {}", e);
+            status = 1;
+        }
+    }
+
+    if args.verify {
+        let discrepancies = match verify::verify(&transaction_engine) {
+            Ok(discrepancies) => discrepancies,
+            Err(e) => {
+                error!("Failed to verify the engine state: {}", e);
+                return 1;
+            }
+        };
+        for discrepancy in &discrepancies {
+            error!("Verification failed: {}", discrepancy);
+        }
+        if !discrepancies.is_empty() {
+            status = 1;
+        }
+    }
+    status
+}
+
+// everything an output needs is read from the storage before any of it is written
+fn read_all<T>(items: impl Iterator<Item=StorageResult<T>>) -> io::Result<Vec<T>> {
+    Ok(items.collect::<StorageResult<Vec<T>>>()?)
+}
+
+// exits with 1 when the input cannot be processed or the output cannot be written
+fn inspect_client(cx: ClientID, config: &Config, args: &EngineArgs) -> i32 {
+    let Run{engine: transaction_engine, ..} = match run(config, args, None) {
+        Ok(run) => run,
+        Err(e) => {
+            error!("{}", e);
+            return 1;
+        }
+    };
+    let mut stdout = std::io::stdout().lock();
+    let result = read_all(transaction_engine.get_accounts()).and_then(|accounts| {
+        let account = accounts.into_iter().find(|account| account.client_id == cx);
+        if account.is_none() {
+            warn!("No account for client {}", cx);
+        }
+        output::output_flagged_accounts(&mut stdout, config.decimals, account.into_iter())
+    })
+        .and_then(|_| writeln!(stdout))
+        .and_then(|_| output::output_events(&mut stdout, config.decimals, transaction_engine.get_history(cx)?));
+    if let Err(e) = result {
+        error!("Failed to write the output: {}", e);
+        return 1;
+    }
+    0
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let engine_args = match &cli.command {
+        None => Some(&cli.process.engine),
+        Some(Command::Process(args)) => Some(&args.engine),
+        Some(Command::InspectClient{engine, ..}) => Some(engine),
+        Some(_) => None
+    };
+    let config = cli.config.as_ref().map_or(Ok(Config::default()), |path| Config::load(path)
+        .map_err(|e| format!("Failed to load the config: {}", e)))
+        .and_then(|mut config| {
+            if cli.verbose > 0 {
+                config.verbosity = cli.verbose;
+            }
+            config.decimals = cli.decimals.unwrap_or(config.decimals);
+            if let Some(args) = engine_args {
+                apply_overrides(&mut config, args)?;
+            }
+            config.check().map(|_| config)
+        });
+
+    let verbosity = config.as_ref().map_or(cli.verbose, |config| config.verbosity);
+    stderrlog::new().module(module_path!()).verbosity(verbosity as usize).init().unwrap();
+
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if cli.print_config {
+        match config.to_toml() {
+            Ok(toml) => print!("{}", toml),
+            Err(e) => {
+                error!("Failed to print the config: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    std::process::exit(match &cli.command {
+        None => process(&config, &cli.process),
+        Some(Command::Process(args)) => process(&config, args),
+        Some(Command::Validate{path}) => validate(path, &config),
+        Some(Command::Diff{before, after, tolerance}) => diff(before, after, *tolerance),
+        Some(Command::InspectClient{client, engine}) => inspect_client(*client, &config, engine)
+    })
+}
@@ -1,16 +1,179 @@
-use crate::transaction_engine::ClientAccount;
+use std::borrow::Cow;
+use std::io::{self, Write};
+use crate::transaction_engine::{AccountChange, ClientAccount};
+use crate::account::Account;
+use crate::events::Event;
+use crate::ledger::{Balance, Posting};
+use crate::common_types::{ClientID, Rejection};
+use crate::disputes::{DisputeCase, OpenDispute};
+use crate::account_diff::ClientDiff;
+use crate::lint::{self, Issue};
+use crate::scale::Scale;
 
-pub fn output_accounts<'a>(accounts: impl Iterator<Item=ClientAccount<'a>>) {
-    println!("client,available,held,total,locked");
+pub fn output_accounts<W: Write>(writer: &mut W, scale: Scale, accounts: impl Iterator<Item=ClientAccount>) -> io::Result<()> {
+    writeln!(writer, "client,available,held,total,locked")?;
     for account in accounts {
-        output_account(account);
+        output_account(writer, scale, &account)?;
+        writeln!(writer)?;
     }
+    Ok(())
 }
 
-fn output_account(account: ClientAccount) {
-    println!("{},{},{},{},{}", account.client_id,
-             account.account.available() as f64 / 10000.0,
-             account.account.held() as f64 / 10000.0,
-             account.account.total() as f64 / 10000.0,
-             account.account.is_locked());
-}
\ No newline at end of file
+// same as `output_accounts` with the reason the fraud heuristics flagged each client, empty if it was not
+pub fn output_flagged_accounts<W: Write>(writer: &mut W, scale: Scale, accounts: impl Iterator<Item=ClientAccount>) -> io::Result<()> {
+    writeln!(writer, "client,available,held,total,locked,flag")?;
+    for account in accounts {
+        output_account(writer, scale, &account)?;
+        writeln!(writer, ",{}", account.flag.map(|flag| flag.reason).unwrap_or_default())?;
+    }
+    Ok(())
+}
+
+fn output_account<W: Write>(writer: &mut W, scale: Scale, account: &ClientAccount) -> io::Result<()> {
+    write!(writer, "{},{},{},{},{}", account.client_id,
+             scale.format(account.account.available()),
+             scale.format(account.account.held()),
+             scale.format(account.account.total()),
+             account.account.is_locked())
+}
+
+// the accounts a what-if simulation would change, the `_before` columns are empty for a new client
+pub fn output_account_changes<'a, W: Write>(writer: &mut W, scale: Scale, changes: impl Iterator<Item=&'a AccountChange>) -> io::Result<()> {
+    writeln!(writer, "client,available_before,available,held_before,held,total_before,total,locked_before,locked")?;
+    for change in changes {
+        let before = |field: &dyn Fn(&Account) -> String| change.before.as_ref().map(field).unwrap_or_default();
+        writeln!(writer, "{},{},{},{},{},{},{},{},{}", change.client_id,
+                 before(&|account| scale.format(account.available())),
+                 scale.format(change.after.available()),
+                 before(&|account| scale.format(account.held())),
+                 scale.format(change.after.held()),
+                 before(&|account| scale.format(account.total())),
+                 scale.format(change.after.total()),
+                 before(&|account| account.is_locked().to_string()),
+                 change.after.is_locked())?;
+    }
+    Ok(())
+}
+
+// the amount columns are deltas, after minus before, the lock columns are empty for a missing account
+pub fn output_account_diff<'a, W: Write>(writer: &mut W, diffs: impl Iterator<Item=&'a ClientDiff>) -> io::Result<()> {
+    writeln!(writer, "client,change,available_delta,held_delta,total_delta,locked_before,locked")?;
+    for diff in diffs {
+        let deltas = diff.deltas();
+        writeln!(writer, "{},{},{},{},{},{},{}", diff.client_id(), diff.name(), deltas.available, deltas.held, deltas.total,
+                 diff.before().map(|account| account.locked.to_string()).unwrap_or_default(),
+                 diff.after().map(|account| account.locked.to_string()).unwrap_or_default())?;
+    }
+    Ok(())
+}
+
+// every issue of an input file, then the number of issues of each kind
+pub fn output_lint_report<W: Write>(writer: &mut W, issues: &[Issue]) -> io::Result<()> {
+    writeln!(writer, "line,issue,message")?;
+    for issue in issues {
+        writeln!(writer, "{},{},\"{}\"", issue.line, issue.kind, issue.message.replace('"', "\"\""))?;
+    }
+    writeln!(writer)?;
+    writeln!(writer, "issue,count")?;
+    for (kind, count) in lint::summary(issues) {
+        writeln!(writer, "{},{}", kind, count)?;
+    }
+    Ok(())
+}
+
+pub fn output_statement<W: Write>(writer: &mut W, scale: Scale, statement: impl Iterator<Item=(Posting, Balance)>) -> io::Result<()> {
+    writeln!(writer, "type,tx,available,held,total")?;
+    for (posting, balance) in statement {
+        writeln!(writer, "{},{},{},{},{}", posting.kind(), posting.tx.map(|tx| tx.to_string()).unwrap_or_default(),
+                 scale.format(balance.available),
+                 scale.format(balance.held),
+                 scale.format(balance.total()))?;
+    }
+    Ok(())
+}
+
+pub fn output_events<W: Write>(writer: &mut W, scale: Scale, history: impl Iterator<Item=(Event, Account)>) -> io::Result<()> {
+    writeln!(writer, "event,tx,amount,available,held,total,locked")?;
+    for (event, account) in history {
+        writeln!(writer, "{},{},{},{},{},{},{}", event, event.tx().map(|tx| tx.to_string()).unwrap_or_default(),
+                 event.amount().map(|amount| scale.format(amount)).unwrap_or_default(),
+                 scale.format(account.available()),
+                 scale.format(account.held()),
+                 scale.format(account.total()),
+                 account.is_locked())?;
+    }
+    Ok(())
+}
+
+pub fn output_balance<W: Write>(writer: &mut W, scale: Scale, client_id: ClientID, balance: Balance) -> io::Result<()> {
+    writeln!(writer, "client,available,held,total")?;
+    writeln!(writer, "{},{},{},{}", client_id,
+             scale.format(balance.available),
+             scale.format(balance.held),
+             scale.format(balance.total()))
+}
+
+pub fn output_dispute_cases<W: Write>(writer: &mut W, scale: Scale, cases: impl Iterator<Item=DisputeCase>) -> io::Result<()> {
+    writeln!(writer, "tx,client,status,disputed,charged_back,reason_code,case_id,deadline")?;
+    for case in cases {
+        writeln!(writer, "{},{},{},{},{},{},{},{}", case.tx, case.client_id, case.status,
+                 scale.format(case.disputed),
+                 scale.format(case.charged_back),
+                 quoted(case.info.reason_code.as_deref().unwrap_or_default()),
+                 quoted(case.info.case_id.as_deref().unwrap_or_default()),
+                 quoted(case.info.deadline.as_deref().unwrap_or_default()))?;
+    }
+    Ok(())
+}
+
+// a row of the rejections report
+pub enum RejectedRow<'a> {
+    // refused by the engine
+    Rejected(&'a Rejection),
+    // refused by the parser, with its input line and the parse error
+    Unparsable(u64, &'a str)
+}
+
+// the rejections report is written row by row while the input is processed, see `output_rejection`
+pub fn output_rejections_header<W: Write>(writer: &mut W, extra_columns: &[String]) -> io::Result<()> {
+    write!(writer, "line,tx,client,type,amount,reason")?;
+    for column in extra_columns {
+        write!(writer, ",{}", quoted(column))?;
+    }
+    writeln!(writer)
+}
+
+// a refused row with its input line, followed by the columns of its row the parser passed through. The transaction
+// columns of a row the parser refused are empty and its reason is the parse error
+pub fn output_rejection<W: Write>(writer: &mut W, scale: Scale, row: RejectedRow, extra: &[String]) -> io::Result<()> {
+    match row {
+        RejectedRow::Rejected(rejection) => {
+            let rejected = &rejection.transaction;
+            write!(writer, "{},{},{},{},{},{}", rejection.line, rejected.tx(), rejected.client(), rejected.name(),
+                   rejected.amount().map(|amount| scale.format(amount)).unwrap_or_default(),
+                   rejection.reason)?;
+        },
+        RejectedRow::Unparsable(line, e) => write!(writer, "{},,,,,{}", line, quoted(e))?
+    }
+    for value in extra {
+        write!(writer, ",{}", quoted(value))?;
+    }
+    writeln!(writer)
+}
+
+// a value of a partner file, quoted if it holds a delimiter, quote or line break
+fn quoted(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+pub fn output_open_disputes<W: Write>(writer: &mut W, scale: Scale, disputes: impl Iterator<Item=OpenDispute>) -> io::Result<()> {
+    writeln!(writer, "tx,client,amount,line")?;
+    for dispute in disputes {
+        writeln!(writer, "{},{},{},{}", dispute.tx, dispute.client_id, scale.format(dispute.amount), dispute.opened)?;
+    }
+    Ok(())
+}
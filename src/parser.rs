@@ -1,12 +1,20 @@
 // input format:
-// type(str), client(u16), tx(u32), amount(float)
-// type -> (deposit, withdrawal, dispute, resolve, chargeback)
+// type(str), client(u16), tx(u32), amount(decimal, with at most the decimals of the scale)
+// type -> (deposit, withdrawal, dispute, resolve, chargeback, reversal)
+// the amount of a dispute, resolve or chargeback is optional and limits it to part of the deposit
+// optional columns for disputes: reason_code(str), case_id(str), deadline(str)
+// a negative amount is read as 0, or the row is skipped if the input config rejects negative amounts
+// the delimiter, quoting and headers follow the input config, whose mapping renames the columns of partner files to
+// these fields, and the other columns are kept as is for the rejections report
 
 use std::error::Error;
 use serde::Deserialize;
-use csv;
-use crate::common_types::{ClientID, TransactionID, Transaction, Amount};
-use log::{warn, error};
+use crate::common_types::{ClientID, TransactionID, Transaction, DisputeInfo};
+use crate::config::{Config, InputConfig, NegativeAmounts};
+use log::error;
+
+// the fields a column can hold
+pub const FIELDS: [&str; 7] = ["type", "client", "tx", "amount", "reason_code", "case_id", "deadline"];
 
 #[derive(Debug, Deserialize)]
 struct Row {
@@ -15,52 +23,170 @@ struct Row {
     client: ClientID,
     #[serde(rename(deserialize="tx"))]
     transaction: TransactionID,
-    amount: Option<f64>
+    amount: Option<String>,
+    reason_code: Option<String>,
+    case_id: Option<String>,
+    deadline: Option<String>
 }
 
 pub fn parse_csv<P: AsRef<std::path::Path>>(path: P) -> Result<Vec<Transaction>, Box<dyn Error>> {
-    let mut reader = csv::Reader::from_path(path)?;
-    let rows = reader.deserialize();
+    parse_csv_with(path, &Config::default())
+}
 
-    Ok(rows.filter_map(|row: Result<Row, csv::Error>| {
-        if row.is_err() {
-            error!("Error while parsing the row: {}", row.err().unwrap());
-            return None;
-        }
-        let row = row.unwrap();
-        let amount = if let Some(amount) = row.amount {
-            if amount < 0.0 {
-                Some(0)
-            } else {
-                Some((amount * 10000.0) as Amount) // fixed floating point with 4 decimals
-            }
-        } else {
+pub fn parse_csv_with<P: AsRef<std::path::Path>>(path: P, config: &Config) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    parse_reader_with(std::fs::File::open(path)?, config)
+}
+
+pub fn parse_reader<R: std::io::Read>(input: R) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    parse_reader_with(input, &Config::default())
+}
+
+pub fn parse_reader_with<R: std::io::Read>(input: R, config: &Config) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    Ok(parse_lines(input, config)?.filter_map(|(line, transaction)| match transaction {
+        Ok(transaction) => Some(transaction),
+        Err(e) => {
+            error!("Skipping line {}: {}", line, e);
             None
-        };
-        match row.transaction_type.as_str() {
-            "deposit" => {
-                if let Some(amount) = amount {
-                    Some(Transaction::Deposit(row.transaction, row.client, amount))
-                } else {
-                    warn!("No amount for transaction type 'deposit', skipping");
-                    None
-                }
-            },
-            "withdrawal" => {
-                if let Some(amount) = amount {
-                    Some(Transaction::Withdrawal(row.transaction, row.client, amount))
-                } else {
-                    warn!("No amount for transaction type 'withdrawal', skipping");
-                    None
-                }
-            },
-            "dispute" => Some(Transaction::Dispute(row.transaction, row.client)),
-            "resolve" => Some(Transaction::Resolve(row.transaction, row.client)),
-            "chargeback" => Some(Transaction::Chargeback(row.transaction, row.client)),
-            _ => {
-                warn!("Transaction type '{}' not supported, skipping", row.transaction_type);
-                None
-            }
         }
     }).collect())
-}
\ No newline at end of file
+}
+
+// a CSV reader of the input dialect, the columns are read with `Columns::read`
+pub fn csv_reader<R: std::io::Read>(input: R, config: &InputConfig) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .delimiter(config.delimiter as u8)
+        .quote(config.quote as u8)
+        .quoting(config.quoting)
+        .has_headers(config.headers)
+        .from_reader(input)
+}
+
+// the field held by every column of the input, after the mapping, the columns holding none are passed through
+#[derive(Debug, Clone, Default)]
+pub struct Columns {
+    fields: csv::StringRecord,
+    extra: Vec<usize>
+}
+
+impl Columns {
+    // the headers of the input, or the configured columns of an input without headers
+    pub fn read<R: std::io::Read>(reader: &mut csv::Reader<R>, config: &InputConfig) -> csv::Result<Self> {
+        let names = if config.headers { reader.headers()?.clone() } else { config.columns.iter().collect() };
+        let fields: csv::StringRecord = names.iter().map(|name| config.mapping.get(name).map_or(name, String::as_str)).collect();
+        let extra = fields.iter().enumerate().filter(|(_, field)| !FIELDS.contains(field)).map(|(column, _)| column).collect();
+        Ok(Self{fields, extra})
+    }
+
+    pub fn position(&self, field: &str) -> Option<usize> {
+        self.fields.iter().position(|name| name == field)
+    }
+
+    // the names of the passed through columns
+    pub fn extra_names(&self) -> Vec<String> {
+        self.extra.iter().map(|column| self.fields[*column].to_string()).collect()
+    }
+
+    // the values of the passed through columns of a record, empty for a missing value
+    pub fn extra(&self, record: &csv::StringRecord) -> Vec<String> {
+        self.extra.iter().map(|column| record.get(*column).unwrap_or_default().to_string()).collect()
+    }
+}
+
+// every row of the input with its line number, and the transaction it holds or the reason it cannot be processed
+pub fn parse_lines<'a, R: std::io::Read + 'a>(input: R, config: &'a Config) -> csv::Result<impl Iterator<Item=(u64, Result<Transaction, String>)> + 'a> {
+    Ok(parse_rows(input, config)?.1.map(|(line, transaction, _)| (line, transaction)))
+}
+
+// line number, transaction or the reason it cannot be processed, and the values of the passed through columns
+pub type ParsedRow = (u64, Result<Transaction, String>, Vec<String>);
+
+// the names of the passed through columns, and every row of the input. Headers that cannot be read fail the whole input
+pub fn parse_rows<'a, R: std::io::Read + 'a>(input: R, config: &'a Config) -> csv::Result<(Vec<String>, impl Iterator<Item=ParsedRow> + 'a)> {
+    let mut reader = csv_reader(input, &config.input);
+    let columns = Columns::read(&mut reader, &config.input)?;
+    Ok((columns.extra_names(), reader.into_records().map(move |record| match record {
+        Ok(record) => (record.position().map_or(0, |position| position.line()), parse_record(&record, &columns, config), columns.extra(&record)),
+        Err(e) => (e.position().map_or(0, |position| position.line()), Err(format!("invalid row: {}", e)), Vec::new())
+    })))
+}
+
+pub fn parse_record(record: &csv::StringRecord, columns: &Columns, config: &Config) -> Result<Transaction, String> {
+    record.deserialize(Some(&columns.fields)).map_err(|e| format!("invalid row: {}", e)).and_then(|row| transaction(row, config))
+}
+
+fn transaction(row: Row, config: &Config) -> Result<Transaction, String> {
+    let amount = match row.amount.as_deref().map(str::trim) {
+        Some(amount) if amount.starts_with('-') => match config.input.negative_amounts {
+            NegativeAmounts::Zero => Some(0),
+            NegativeAmounts::Reject => return Err(format!("negative amount {}", amount))
+        },
+        Some(amount) => Some(config.decimals.parse(amount)?),
+        None => None
+    };
+    match Transaction::new(&row.transaction_type, row.transaction, row.client, amount)? {
+        Transaction::Dispute(tx, cx, amount, _) => {
+            let info = DisputeInfo{reason_code: row.reason_code, case_id: row.case_id, deadline: row.deadline};
+            let info = (info != DisputeInfo::default()).then(|| Box::new(info));
+            Ok(Transaction::Dispute(tx, cx, amount, info))
+        },
+        transaction => Ok(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common_types::Transaction;
+    use crate::config::{Config, InputConfig, NegativeAmounts};
+    use crate::scale::Scale;
+    use crate::parser::{parse_lines, parse_reader, parse_reader_with, parse_rows};
+
+    #[test]
+    fn line_numbers() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.5\ndeposit,1,2,\ntransfer,1,3,1\ndeposit,x,4,1\nwithdrawal,1,5,0.5\n";
+        let lines: Vec<(u64, bool)> = parse_lines(input.as_bytes(), &Config::default()).unwrap().map(|(line, transaction)| (line, transaction.is_ok())).collect();
+        assert_eq!(lines, vec![(2, true), (3, false), (4, false), (5, false), (6, true)]);
+        assert_eq!(parse_reader(input.as_bytes()).unwrap(), vec![Transaction::Deposit(1, 1, 15000), Transaction::Withdrawal(5, 1, 5000)]);
+    }
+
+    #[test]
+    fn negative_amounts() {
+        let input = "type,client,tx,amount\ndeposit,1,1,-1\ndeposit,1,2,1\n";
+        assert_eq!(parse_reader(input.as_bytes()).unwrap(), vec![Transaction::Deposit(1, 1, 0), Transaction::Deposit(2, 1, 10000)]);
+        let config = Config{input: InputConfig{negative_amounts: NegativeAmounts::Reject, ..InputConfig::default()}, ..Config::default()};
+        assert_eq!(parse_reader_with(input.as_bytes(), &config).unwrap(), vec![Transaction::Deposit(2, 1, 10000)]);
+    }
+
+    #[test]
+    fn decimals() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.12345678\ndeposit,1,2,0.00001\ndeposit,1,3,2.5\n";
+        assert_eq!(parse_reader(input.as_bytes()).unwrap(), vec![Transaction::Deposit(3, 1, 25000)]);
+        let config = Config{decimals: Scale::new(8).unwrap(), ..Config::default()};
+        assert_eq!(parse_reader_with(input.as_bytes(), &config).unwrap(), vec![
+            Transaction::Deposit(1, 1, 112345678),
+            Transaction::Deposit(2, 1, 1000),
+            Transaction::Deposit(3, 1, 250000000)
+        ]);
+    }
+
+    #[test]
+    fn dialect() {
+        let config = Config::parse("[input]\ndelimiter = \";\"\n[input.mapping]\nkind = \"type\"\ncustomer = \"client\"\n\
+                                    transaction_id = \"tx\"\n").unwrap();
+        let input = "kind;customer;transaction_id;amount;batch;note\ndeposit;1;1;1,5;b1;\"a;b\"\nwithdrawal;1;2;0.5;b2;\n";
+        let (extra, rows) = parse_rows(input.as_bytes(), &config).unwrap();
+        assert_eq!(extra, vec!["batch", "note"]);
+        let rows: Vec<_> = rows.map(|(line, transaction, extra)| (line, transaction.ok(), extra)).collect();
+        assert_eq!(rows[0], (2, None, vec!["b1".to_string(), "a;b".to_string()]));
+        assert_eq!(rows[1], (3, Some(Transaction::Withdrawal(2, 1, 5000)), vec!["b2".to_string(), String::new()]));
+
+        let config = Config::parse("[input]\nheaders = false\nquoting = false\ncolumns = [\"tx\", \"type\", \"client\", \"amount\"]\n").unwrap();
+        assert_eq!(parse_reader_with("1,deposit,2,\"3\"\n2,deposit,2,4\n".as_bytes(), &config).unwrap(), vec![Transaction::Deposit(2, 2, 40000)]);
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let input: &[u8] = b"type,client,tx,\xffamount\ndeposit,1,1,1\n";
+        assert!(parse_rows(input, &Config::default()).is_err());
+        assert!(parse_reader(input).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+// Risk rules evaluated before a transaction is applied, loaded from a TOML file:
+//
+// [max_withdrawal]
+// amount = "1000.0"
+//
+// [max_daily_withdrawals]
+// count = 5
+//
+// [large_deposit_hold]
+// amount = "10000.0"
+// rows = 100
+//
+// The same file configures the fraud heuristics, see heuristics.rs
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use crate::common_types::*;
+use crate::heuristics::{DisputeFrequency, DisputeHistory, DisputeRatio, Flag};
+use crate::scale::{AmountSetting, Scale};
+
+// amounts are kept as written and converted with the scale of the engine
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaxWithdrawal {
+    pub amount: AmountSetting
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaxDailyWithdrawals {
+    pub count: u32
+}
+
+// withdrawals are blocked for `rows` input rows after a deposit of at least `amount`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LargeDepositHold {
+    pub amount: AmountSetting,
+    pub rows: u64
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulesConfig {
+    pub max_withdrawal: Option<MaxWithdrawal>,
+    pub max_daily_withdrawals: Option<MaxDailyWithdrawals>,
+    pub large_deposit_hold: Option<LargeDepositHold>,
+    pub dispute_frequency: Option<DisputeFrequency>,
+    pub dispute_ratio: Option<DisputeRatio>
+}
+
+impl RulesConfig {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        toml::from_str(content).map_err(|e| e.to_string())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        Self::parse(&std::fs::read_to_string(path).map_err(|e| e.to_string())?)
+    }
+
+    pub fn has_heuristics(&self) -> bool {
+        self.dispute_frequency.is_some() || self.dispute_ratio.is_some()
+    }
+
+    // the amounts of the rules at the given scale, refused as an input amount would be
+    pub fn amounts(&self, scale: Scale) -> Result<RuleAmounts, String> {
+        let amount = |amount: &AmountSetting, rule| amount.to_amount(scale).map_err(|e| format!("{}: {}", rule, e));
+        Ok(RuleAmounts {
+            max_withdrawal: self.max_withdrawal.as_ref().map(|rule| amount(&rule.amount, "max_withdrawal")).transpose()?,
+            large_deposit: self.large_deposit_hold.as_ref().map(|rule| amount(&rule.amount, "large_deposit_hold")).transpose()?
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RuleAmounts {
+    pub max_withdrawal: Option<Amount>,
+    pub large_deposit: Option<Amount>
+}
+
+// the rules and the per-client history they need, the history survives a reload
+#[derive(Clone)]
+pub struct RiskRules {
+    config: RulesConfig,
+    scale: Scale,
+    amounts: RuleAmounts,
+    source: Option<(PathBuf, Option<SystemTime>)>,
+    // day and withdrawal count of that day, per client
+    daily_withdrawals: HashMap<ClientID, (u64, u32)>,
+    // input row of the last large deposit, per client
+    large_deposits: HashMap<ClientID, u64>,
+    histories: HashMap<ClientID, DisputeHistory>
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl RiskRules {
+    // `scale` is the scale of the transaction amounts the rule amounts are compared to
+    pub fn new(config: RulesConfig, scale: Scale) -> Result<Self, String> {
+        Ok(Self {
+            amounts: config.amounts(scale)?,
+            config,
+            scale,
+            source: None,
+            daily_withdrawals: HashMap::new(),
+            large_deposits: HashMap::new(),
+            histories: HashMap::new()
+        })
+    }
+
+    // loads the rules from a file, `reload_if_changed` picks up later edits of that file
+    pub fn from_file<P: AsRef<Path>>(path: P, scale: Scale) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let mut rules = Self::new(RulesConfig::load(&path)?, scale)?;
+        let last_modified = modified(&path);
+        rules.source = Some((path, last_modified));
+        Ok(rules)
+    }
+
+    pub fn config(&self) -> &RulesConfig {
+        &self.config
+    }
+
+    // reloads the rules if their file was modified since the last load, returns whether they were reloaded
+    pub fn reload_if_changed(&mut self) -> Result<bool, String> {
+        if let Some((path, last_modified)) = &mut self.source {
+            let current = modified(path);
+            if current != *last_modified {
+                *last_modified = current;
+                // invalid rules leave the current ones in place
+                let config = RulesConfig::load(path)?;
+                self.amounts = config.amounts(self.scale)?;
+                self.config = config;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // `position` is the input row of the transaction and `day` the current day number
+    pub fn check(&self, position: u64, day: u64, transaction: &Transaction) -> Result<(), RejectReason> {
+        if let Transaction::Withdrawal(_, cx, amount) = *transaction {
+            if self.amounts.max_withdrawal.is_some_and(|max_withdrawal| amount > max_withdrawal) {
+                return Err(RejectReason::Rule("max_withdrawal"));
+            }
+            if let Some(rule) = &self.config.max_daily_withdrawals {
+                if let Some((_, count)) = self.daily_withdrawals.get(&cx).filter(|(withdrawal_day, _)| *withdrawal_day == day) {
+                    if *count >= rule.count {
+                        return Err(RejectReason::Rule("max_daily_withdrawals"));
+                    }
+                }
+            }
+            if let Some(rule) = &self.config.large_deposit_hold {
+                if let Some(deposit_position) = self.large_deposits.get(&cx) {
+                    if position - deposit_position <= rule.rows {
+                        return Err(RejectReason::Rule("large_deposit_hold"));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // records a transaction the engine applied, returns a flag if it trips a fraud heuristic
+    pub fn record(&mut self, position: u64, day: u64, transaction: &Transaction) -> Option<Flag> {
+        match *transaction {
+            Transaction::Withdrawal(_, cx, _) => {
+                let entry = self.daily_withdrawals.entry(cx).or_insert((day, 0));
+                if entry.0 != day {
+                    *entry = (day, 0);
+                }
+                entry.1 += 1;
+            },
+            Transaction::Deposit(_, cx, amount) if self.amounts.large_deposit.is_some_and(|large_deposit| amount >= large_deposit) => {
+                self.large_deposits.insert(cx, position);
+            },
+            _ => {}
+        }
+        if !self.config.has_heuristics() {
+            return None;
+        }
+        let window = self.config.dispute_frequency.as_ref().map_or(0, |heuristic| heuristic.transactions);
+        let history = self.histories.entry(transaction.client()).or_default();
+        match transaction {
+            Transaction::Deposit(..) => history.record_deposit(window),
+            Transaction::Dispute(..) => history.record_dispute(window),
+            _ => history.record_other(window)
+        }
+        if !matches!(transaction, Transaction::Dispute(..)) {
+            return None;
+        }
+        self.config.dispute_frequency.as_ref().and_then(|heuristic| history.check_frequency(heuristic))
+            .or_else(|| self.config.dispute_ratio.as_ref().and_then(|heuristic| history.check_ratio(heuristic)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common_types::{RejectReason, Transaction};
+    use crate::rules::{LargeDepositHold, MaxDailyWithdrawals, MaxWithdrawal, RiskRules, RuleAmounts, RulesConfig};
+    use crate::scale::Scale;
+
+    #[test]
+    fn parse_config() {
+        let config = RulesConfig::parse("
+            [max_withdrawal]
+            amount = \"1.5\"
+            [large_deposit_hold]
+            amount = \"100.0\"
+            rows = 3
+        ").unwrap();
+        assert_eq!(config, RulesConfig {
+            max_withdrawal: Some(MaxWithdrawal{amount: "1.5".parse().unwrap()}),
+            max_daily_withdrawals: None,
+            large_deposit_hold: Some(LargeDepositHold{amount: "100.0".parse().unwrap(), rows: 3}),
+            dispute_frequency: None,
+            dispute_ratio: None
+        });
+        assert_eq!(config.amounts(Scale::default()), Ok(RuleAmounts{max_withdrawal: Some(15000), large_deposit: Some(1000000)}));
+        assert!(config.amounts(Scale::new(0).unwrap()).is_err());
+        assert!(RulesConfig::parse("[max_deposit]\namount = \"1.0\"").is_err());
+        assert!(RulesConfig::parse("[max_withdrawal]\namount = 1.5").is_err());
+        assert!(RulesConfig::parse("[max_withdrawal]\namount = \"1e3\"").is_err());
+    }
+
+    #[test]
+    fn max_withdrawal() {
+        let rules = RiskRules::new(RulesConfig{max_withdrawal: Some(MaxWithdrawal{amount: "0.001".parse().unwrap()}), ..Default::default()}, Scale::default()).unwrap();
+        assert_eq!(rules.check(1, 0, &Transaction::Withdrawal(1, 1, 10)), Ok(()));
+        assert_eq!(rules.check(1, 0, &Transaction::Withdrawal(1, 1, 11)), Err(RejectReason::Rule("max_withdrawal")));
+        assert_eq!(rules.check(1, 0, &Transaction::Deposit(1, 1, 11)), Ok(()));
+    }
+
+    #[test]
+    fn max_daily_withdrawals() {
+        let mut rules = RiskRules::new(RulesConfig{max_daily_withdrawals: Some(MaxDailyWithdrawals{count: 2}), ..Default::default()}, Scale::default()).unwrap();
+        for position in 1..=2 {
+            let withdrawal = Transaction::Withdrawal(position as u32, 1, 1);
+            assert_eq!(rules.check(position, 0, &withdrawal), Ok(()));
+            rules.record(position, 0, &withdrawal);
+        }
+        let withdrawal = Transaction::Withdrawal(3, 1, 1);
+        assert_eq!(rules.check(3, 0, &withdrawal), Err(RejectReason::Rule("max_daily_withdrawals")));
+        assert_eq!(rules.check(3, 0, &Transaction::Withdrawal(3, 2, 1)), Ok(()));
+        assert_eq!(rules.check(3, 1, &withdrawal), Ok(()));
+    }
+
+    #[test]
+    fn large_deposit_hold() {
+        let mut rules = RiskRules::new(RulesConfig{large_deposit_hold: Some(LargeDepositHold{amount: "0.01".parse().unwrap(), rows: 2}), ..Default::default()}, Scale::default()).unwrap();
+        rules.record(1, 0, &Transaction::Deposit(1, 1, 99));
+        assert_eq!(rules.check(2, 0, &Transaction::Withdrawal(2, 1, 1)), Ok(()));
+        rules.record(2, 0, &Transaction::Deposit(3, 1, 100));
+        assert_eq!(rules.check(4, 0, &Transaction::Withdrawal(4, 1, 1)), Err(RejectReason::Rule("large_deposit_hold")));
+        assert_eq!(rules.check(5, 0, &Transaction::Withdrawal(5, 1, 1)), Ok(()));
+    }
+
+    #[test]
+    fn reload() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"[max_withdrawal]\namount = \"1.0\"\n").unwrap();
+        let mut rules = RiskRules::from_file(file.path(), Scale::default()).unwrap();
+        assert_eq!(rules.reload_if_changed(), Ok(false));
+        std::fs::write(file.path(), "[max_daily_withdrawals]\ncount = 1\n").unwrap();
+        let modified = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+        file.as_file().set_modified(modified).unwrap();
+        assert_eq!(rules.reload_if_changed(), Ok(true));
+        assert_eq!(rules.config(), &RulesConfig{max_daily_withdrawals: Some(MaxDailyWithdrawals{count: 1}), ..Default::default()});
+        // an amount the scale cannot hold keeps the current rules
+        std::fs::write(file.path(), "[max_withdrawal]\namount = \"0.00001\"\n").unwrap();
+        file.as_file().set_modified(modified + std::time::Duration::from_secs(10)).unwrap();
+        assert!(rules.reload_if_changed().is_err());
+        assert_eq!(rules.config(), &RulesConfig{max_daily_withdrawals: Some(MaxDailyWithdrawals{count: 1}), ..Default::default()});
+    }
+}
@@ -0,0 +1,190 @@
+// The fixed point scale shared by parsing, arithmetic and formatting: an amount of `n` units is `n / 10^decimals`.
+// 4 decimals by default, the `wide-amounts` feature makes amounts 128 bits wide for deployments needing more digits.
+
+use std::fmt::{self, Write};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use crate::common_types::Amount;
+
+// the largest scale that still leaves room for an integer part
+pub const MAX_DECIMALS: u32 = Amount::MAX.ilog10() - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecimalError {
+    Invalid,
+    TooManyDigits
+}
+
+// the only decimal parser: splits an unsigned decimal such as "12.5" into its digits and its number of decimals, (125, 1),
+// without going through floating point
+pub fn parse_decimal(decimal: &str) -> Result<(u128, u32), DecimalError> {
+    let (integer, fraction) = decimal.split_once('.').unwrap_or((decimal, ""));
+    if integer.is_empty() && fraction.is_empty() || !integer.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(DecimalError::Invalid);
+    }
+    let digits = integer.bytes().chain(fraction.bytes())
+        .try_fold(0u128, |digits, digit| digits.checked_mul(10)?.checked_add(u128::from(digit - b'0')))
+        .ok_or(DecimalError::TooManyDigits)?;
+    Ok((digits, fraction.len() as u32))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct Scale {
+    decimals: u32
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self{decimals: 4}
+    }
+}
+
+impl TryFrom<u32> for Scale {
+    type Error = String;
+
+    fn try_from(decimals: u32) -> Result<Self, Self::Error> {
+        Self::new(decimals)
+    }
+}
+
+impl From<Scale> for u32 {
+    fn from(scale: Scale) -> Self {
+        scale.decimals
+    }
+}
+
+impl Scale {
+    pub fn new(decimals: u32) -> Result<Self, String> {
+        if decimals > MAX_DECIMALS {
+            return Err(format!("{} decimals is more than the {} amounts can hold", decimals, MAX_DECIMALS));
+        }
+        Ok(Self{decimals})
+    }
+
+    pub fn decimals(&self) -> u32 {
+        self.decimals
+    }
+
+    // the number of units in 1
+    pub fn factor(&self) -> Amount {
+        Amount::pow(10, self.decimals)
+    }
+
+    // parses an amount such as "12.5" exactly, refusing more decimals than the scale
+    pub fn parse(&self, amount: &str) -> Result<Amount, String> {
+        let too_large = || format!("amount '{}' is too large", amount);
+        let (digits, decimals) = parse_decimal(amount).map_err(|e| match e {
+            DecimalError::Invalid => format!("invalid amount '{}'", amount),
+            DecimalError::TooManyDigits => too_large()
+        })?;
+        if decimals > self.decimals {
+            return Err(format!("amount '{}' has more than {} decimals", amount, self.decimals));
+        }
+        10u128.checked_pow(self.decimals - decimals).and_then(|factor| digits.checked_mul(factor))
+            .and_then(|units| Amount::try_from(units).ok())
+            .ok_or_else(too_large)
+    }
+
+    // the shortest exact decimal of an amount, "12.5" rather than "12.5000"
+    pub fn format(&self, amount: Amount) -> String {
+        let mut formatted = (amount / self.factor()).to_string();
+        let fraction = amount % self.factor();
+        if fraction > 0 {
+            let digits = format!("{:0>width$}", fraction, width = self.decimals as usize);
+            write!(formatted, ".{}", digits.trim_end_matches('0')).unwrap();
+        }
+        formatted
+    }
+
+}
+
+// an amount of a config file or a command line option, kept as written until the scale it is read at is known
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AmountSetting(String);
+
+impl AmountSetting {
+    // refuses an amount with more decimals than the scale as any input amount
+    pub fn to_amount(&self, scale: Scale) -> Result<Amount, String> {
+        scale.parse(&self.0)
+    }
+}
+
+impl FromStr for AmountSetting {
+    type Err = String;
+
+    fn from_str(amount: &str) -> Result<Self, Self::Err> {
+        match parse_decimal(amount) {
+            Err(DecimalError::Invalid) => Err(format!("invalid amount '{}'", amount)),
+            _ => Ok(Self(amount.to_string()))
+        }
+    }
+}
+
+impl TryFrom<String> for AmountSetting {
+    type Error = String;
+
+    fn try_from(amount: String) -> Result<Self, Self::Error> {
+        amount.parse()
+    }
+}
+
+impl From<AmountSetting> for String {
+    fn from(amount: AmountSetting) -> Self {
+        amount.0
+    }
+}
+
+impl fmt::Display for AmountSetting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scale::{parse_decimal, AmountSetting, DecimalError, Scale, MAX_DECIMALS};
+
+    #[test]
+    fn decimals() {
+        assert_eq!(parse_decimal("12.50"), Ok((1250, 2)));
+        assert_eq!(parse_decimal(".5"), Ok((5, 1)));
+        assert_eq!(parse_decimal("7"), Ok((7, 0)));
+        assert_eq!(parse_decimal("."), Err(DecimalError::Invalid));
+        assert_eq!(parse_decimal("-1"), Err(DecimalError::Invalid));
+        assert_eq!(parse_decimal(" 1"), Err(DecimalError::Invalid));
+        assert_eq!(parse_decimal(&"9".repeat(40)), Err(DecimalError::TooManyDigits));
+    }
+
+    #[test]
+    fn parse_and_format() {
+        let scale = Scale::default();
+        assert_eq!(scale.parse("1.5"), Ok(15000));
+        assert_eq!(scale.parse(".0001"), Ok(1));
+        assert_eq!(scale.parse("2."), Ok(20000));
+        assert!(scale.parse("0.00001").is_err());
+        assert!(scale.parse("1e3").is_err());
+        assert!(scale.parse("-1").is_err());
+        assert!(scale.parse("9999999999999999999999999999999999999999").is_err());
+        assert_eq!(scale.format(15000), "1.5");
+        assert_eq!(scale.format(1), "0.0001");
+        assert_eq!(scale.format(0), "0");
+        assert_eq!(scale.format(20000), "2");
+
+        let scale = Scale::new(8).unwrap();
+        assert_eq!(scale.parse("0.00000001"), Ok(1));
+        assert_eq!(scale.format(123456789), "1.23456789");
+        assert_eq!(Scale::new(0).unwrap().parse("12"), Ok(12));
+        assert!(Scale::new(MAX_DECIMALS + 1).is_err());
+    }
+
+    #[test]
+    fn amount_settings() {
+        let amount: AmountSetting = "12.005".parse().unwrap();
+        assert_eq!(amount.to_amount(Scale::default()), Ok(120050));
+        assert!(amount.to_amount(Scale::new(2).unwrap()).is_err());
+        assert!("1e3".parse::<AmountSetting>().is_err());
+        assert!("-1".parse::<AmountSetting>().is_err());
+    }
+}
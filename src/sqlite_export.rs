@@ -0,0 +1,184 @@
+// Export of the final engine state to a SQLite database for SQL analysis.
+// The schema is versioned with `PRAGMA user_version`, each entry of MIGRATIONS upgrades it by one version.
+// Amounts are stored like in the SQLite storage, see `SqlAmount`.
+
+use std::collections::HashMap;
+use std::path::Path;
+use rusqlite::{params, Connection};
+use crate::common_types::{Amount, TransactionID};
+use crate::ledger::EntryKind;
+use crate::sqlite_storage::SqlAmount;
+use crate::storage::StorageResult;
+use crate::transaction_engine::TransactionEngine;
+
+const MIGRATIONS: &[&str] = &[
+    // version 1
+    "
+    CREATE TABLE accounts (
+        client INTEGER PRIMARY KEY,
+        available INTEGER NOT NULL,
+        held INTEGER NOT NULL,
+        total INTEGER NOT NULL,
+        locked INTEGER NOT NULL,
+        flag TEXT
+    );
+    CREATE TABLE transactions (
+        id INTEGER PRIMARY KEY,
+        tx INTEGER NOT NULL,
+        client INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        dispute_status TEXT NOT NULL,
+        reversed INTEGER NOT NULL
+    );
+    CREATE INDEX transactions_client ON transactions (client, tx);
+    CREATE TABLE rejections (
+        position INTEGER PRIMARY KEY,
+        line INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        client INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        amount INTEGER,
+        reason TEXT NOT NULL
+    );
+    "
+];
+
+pub fn schema_version(connection: &Connection) -> rusqlite::Result<u32> {
+    connection.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+// brings the database schema up to the latest version
+pub fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let version = schema_version(connection)?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index as u32 + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+pub fn export_sqlite<P: AsRef<Path>>(engine: &TransactionEngine, path: P) -> StorageResult<()> {
+    let mut connection = Connection::open(path)?;
+    export(engine, &mut connection)
+}
+
+// replaces the content of the exported tables with the engine state
+pub fn export(engine: &TransactionEngine, connection: &mut Connection) -> StorageResult<()> {
+    migrate(connection)?;
+    let transaction = connection.transaction()?;
+    transaction.execute_batch("DELETE FROM accounts; DELETE FROM transactions; DELETE FROM rejections;")?;
+    {
+        let mut insert_account = transaction.prepare(
+            "INSERT INTO accounts (client, available, held, total, locked, flag) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+        let mut insert_transaction = transaction.prepare(
+            "INSERT INTO transactions (tx, client, type, amount, dispute_status, reversed) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+        for client in engine.get_accounts() {
+            let client = client?;
+            let account = client.account;
+            insert_account.execute(params![client.client_id, SqlAmount(account.available()), SqlAmount(account.held()),
+                                           SqlAmount(account.total()), account.is_locked(), client.flag.map(|flag| flag.reason)])?;
+            // the transaction history is rebuilt from the client postings, disputes and reversals update their transaction
+            let mut history: Vec<(TransactionID, EntryKind, Amount, &str, bool)> = Vec::new();
+            let mut rows: HashMap<TransactionID, usize> = HashMap::new();
+            for posting in engine.get_postings(client.client_id) {
+                let posting = posting?;
+                // the interest belongs to no transaction
+                let Some(tx) = posting.tx else {
+                    continue;
+                };
+                let status = match posting.kind() {
+                    kind @ (EntryKind::Deposit | EntryKind::Withdrawal) => {
+                        rows.insert(tx, history.len());
+                        history.push((tx, kind, posting.amount, "none", false));
+                        continue;
+                    },
+                    EntryKind::Reversal => {
+                        if let Some(index) = rows.get(&tx) {
+                            history[*index].4 = true;
+                        }
+                        continue;
+                    },
+                    EntryKind::Dispute => "disputed",
+                    EntryKind::Resolve => "resolved",
+                    EntryKind::Chargeback => "charged_back",
+                    EntryKind::Fee | EntryKind::Interest | EntryKind::Other => continue
+                };
+                if let Some(index) = rows.get(&tx) {
+                    history[*index].3 = status;
+                }
+            }
+            for (tx, kind, amount, status, reversed) in history {
+                insert_transaction.execute(params![tx, client.client_id, kind.to_string(), SqlAmount(amount), status, reversed])?;
+            }
+        }
+        let mut insert_rejection = transaction.prepare(
+            "INSERT INTO rejections (position, tx, client, type, amount, reason, line) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+        for rejection in engine.get_rejections() {
+            let rejection = rejection?;
+            let rejected = &rejection.transaction;
+            insert_rejection.execute(params![rejection.position as i64, rejected.tx(), rejected.client(), rejected.name(),
+                                             rejected.amount().map(SqlAmount), rejection.reason.to_string(), rejection.line as i64])?;
+        }
+    }
+    Ok(transaction.commit()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+    use crate::common_types::Transaction;
+    use crate::sqlite_export::{export, migrate, schema_version, MIGRATIONS};
+    use crate::transaction_engine::TransactionEngine;
+
+    #[test]
+    fn migrations() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        assert_eq!(schema_version(&connection).unwrap(), 0);
+        migrate(&mut connection).unwrap();
+        assert_eq!(schema_version(&connection).unwrap(), MIGRATIONS.len() as u32);
+        migrate(&mut connection).unwrap();
+        assert_eq!(schema_version(&connection).unwrap(), MIGRATIONS.len() as u32);
+    }
+
+    #[test]
+    fn export_engine() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 12));
+        te.process_transaction(Transaction::Deposit(2, 1, 30));
+        te.process_transaction(Transaction::Deposit(3, 2, 10));
+        te.process_transaction(Transaction::Withdrawal(4, 1, 2));
+        te.process_transaction(Transaction::Reversal(4, 1));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Chargeback(1, 1, None));
+        te.process_transaction(Transaction::Dispute(3, 2, None, None));
+        te.process_transaction(Transaction::Withdrawal(5, 2, 20));
+        let mut connection = Connection::open_in_memory().unwrap();
+        export(&te, &mut connection).unwrap();
+        // exporting twice replaces the previous content
+        export(&te, &mut connection).unwrap();
+
+        let account: (i64, i64, i64, bool) = connection.query_row(
+            "SELECT available, held, total, locked FROM accounts WHERE client = 1", [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap();
+        assert_eq!(account, (30, 0, 30, true));
+
+        let mut statement = connection.prepare("SELECT tx, type, amount, dispute_status, reversed FROM transactions ORDER BY client, id").unwrap();
+        let transactions: Vec<(u32, String, i64, String, bool)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(transactions, vec![
+            (1, "deposit".to_string(), 12, "charged_back".to_string(), false),
+            (2, "deposit".to_string(), 30, "none".to_string(), false),
+            (4, "withdrawal".to_string(), 2, "none".to_string(), true),
+            (3, "deposit".to_string(), 10, "disputed".to_string(), false)
+        ]);
+
+        let rejection: (i64, String, Option<i64>, String) = connection.query_row(
+            "SELECT position, type, amount, reason FROM rejections", [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap();
+        assert_eq!(rejection, (9, "withdrawal".to_string(), Some(20), "insufficient_funds".to_string()));
+    }
+}
@@ -0,0 +1,614 @@
+// Embedded on-disk storage, the engine state lives in a SQLite database instead of memory.
+// Writes are grouped in database transactions, committed at the first row boundary (`set_position`) past
+// WRITES_PER_COMMIT writes and by `flush`, so a row is never durable in part. The position of the last processed row is
+// kept in memory and written with every commit. A failed write, and dropping the storage, roll back what was not
+// committed.
+// The schema is versioned with `PRAGMA user_version`, a new database gets the latest one and a newer one is refused.
+// Amounts are integers of the scale recorded in the database, a database is refused under another scale. An amount too
+// wide for a SQLite integer is stored as a 16 byte big endian blob, text would be turned into a lossy real by the
+// column affinity.
+
+use std::path::Path;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, Value, ValueRef};
+use crate::common_types::*;
+use crate::account::Account;
+use crate::disputes::DisputeCase;
+use crate::events::Event;
+use crate::heuristics::Flag;
+use crate::ledger::Balance;
+use crate::scale::Scale;
+use crate::storage::{Storage, StorageError, StorageResult};
+use crate::transaction_store::InnerTransaction;
+
+const WRITES_PER_COMMIT: usize = 10000;
+
+// each entry upgrades the schema by one version, the version is kept in `PRAGMA user_version`
+const MIGRATIONS: &[&str] = &[
+    // version 1
+    "
+    CREATE TABLE accounts (
+        client INTEGER PRIMARY KEY,
+        available INTEGER NOT NULL,
+        held INTEGER NOT NULL,
+        locked INTEGER NOT NULL
+    );
+    CREATE TABLE transactions (
+        tx INTEGER PRIMARY KEY,
+        client INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        disputed INTEGER NOT NULL,
+        withdrawal INTEGER NOT NULL,
+        stamp INTEGER NOT NULL,
+        expired INTEGER NOT NULL DEFAULT 0,
+        reversed INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX transactions_stamp ON transactions (stamp) WHERE expired = 0 AND reversed = 0 AND disputed = 0;
+    CREATE TABLE events (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        client INTEGER NOT NULL,
+        event TEXT NOT NULL,
+        tx INTEGER,
+        amount INTEGER NOT NULL
+    );
+    CREATE INDEX events_client ON events (client, id);
+    CREATE TABLE meta (
+        key TEXT PRIMARY KEY,
+        value INTEGER NOT NULL
+    );
+    CREATE TABLE dispute_cases (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        tx INTEGER NOT NULL,
+        client INTEGER NOT NULL,
+        opened INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        disputed INTEGER NOT NULL,
+        charged_back INTEGER NOT NULL,
+        reason_code TEXT,
+        case_id TEXT,
+        deadline TEXT
+    );
+    CREATE UNIQUE INDEX dispute_cases_open ON dispute_cases (tx) WHERE status = 'open';
+    CREATE TABLE flags (
+        client INTEGER PRIMARY KEY,
+        reason TEXT NOT NULL,
+        locked INTEGER NOT NULL
+    );
+    CREATE TABLE rejections (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        position INTEGER NOT NULL,
+        line INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        client INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        amount INTEGER,
+        reason TEXT NOT NULL
+    );
+    CREATE TABLE interest (
+        client INTEGER PRIMARY KEY,
+        period INTEGER NOT NULL
+    );
+    "
+];
+
+pub fn schema_version(connection: &Connection) -> rusqlite::Result<u32> {
+    connection.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+// brings the database schema up to the latest version, a database written by a newer version is refused
+pub fn migrate(connection: &mut Connection) -> StorageResult<()> {
+    let version = schema_version(connection)?;
+    if version as usize > MIGRATIONS.len() {
+        return Err(StorageError::Incompatible(format!("schema version {} is newer than the supported version {}", version, MIGRATIONS.len())));
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index as u32 + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+// an amount as stored, see the module comment
+pub(crate) struct SqlAmount(pub(crate) Amount);
+
+impl ToSql for SqlAmount {
+    #[allow(clippy::useless_conversion)]
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match i64::try_from(self.0) {
+            Ok(amount) => ToSqlOutput::from(amount),
+            Err(_) => ToSqlOutput::Owned(Value::Blob(u128::from(self.0).to_be_bytes().to_vec()))
+        })
+    }
+}
+
+impl FromSql for SqlAmount {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let amount = match value {
+            ValueRef::Integer(amount) => u128::try_from(amount).map_err(|_| FromSqlError::OutOfRange(amount))?,
+            ValueRef::Blob(bytes) => u128::from_be_bytes(bytes.try_into().map_err(|_| FromSqlError::InvalidBlobSize{expected_size: 16, blob_size: bytes.len()})?),
+            _ => return Err(FromSqlError::InvalidType)
+        };
+        #[allow(clippy::useless_conversion)]
+        Amount::try_from(amount).map(SqlAmount).map_err(|_| FromSqlError::InvalidType)
+    }
+}
+
+fn amount(row: &Row, index: usize) -> rusqlite::Result<Amount> {
+    Ok(row.get::<_, SqlAmount>(index)?.0)
+}
+
+pub struct SqliteStorage {
+    connection: Connection,
+    pending_writes: usize,
+    position: u64,
+    // the position of the last commit, restored by a rollback
+    committed: u64
+}
+
+fn account_from_row(row: &Row) -> rusqlite::Result<(ClientID, Account)> {
+    let balance = Balance{available: amount(row, 1)?, held: amount(row, 2)?};
+    Ok((row.get(0)?, Account::restore(balance, row.get(3)?)))
+}
+
+fn transaction_from_row(row: &Row) -> rusqlite::Result<(TransactionID, InnerTransaction)> {
+    Ok((row.get(0)?, InnerTransaction {
+        client_id: row.get(1)?,
+        amount: amount(row, 2)?,
+        disputed: amount(row, 3)?,
+        is_withdrawal: row.get(4)?,
+        stamp: row.get::<_, i64>(5)? as u64
+    }))
+}
+
+fn case_from_row(row: &Row) -> rusqlite::Result<DisputeCase> {
+    Ok(DisputeCase {
+        tx: row.get(0)?,
+        client_id: row.get(1)?,
+        opened: row.get::<_, i64>(2)? as u64,
+        status: row.get::<_, String>(3)?.parse()
+            .map_err(|e: String| rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, e.into()))?,
+        disputed: amount(row, 4)?,
+        charged_back: amount(row, 5)?,
+        info: DisputeInfo{reason_code: row.get(6)?, case_id: row.get(7)?, deadline: row.get(8)?}
+    })
+}
+
+// the dispute context of a refused dispute is not stored
+fn rejection_from_row(row: &Row) -> rusqlite::Result<Rejection> {
+    let conversion = |index, e: String| rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, e.into());
+    let amount = row.get::<_, Option<SqlAmount>>(4)?.map(|amount| amount.0);
+    Ok(Rejection {
+        position: row.get::<_, i64>(0)? as u64,
+        line: row.get::<_, i64>(6)? as u64,
+        transaction: Transaction::new(&row.get::<_, String>(3)?, row.get(1)?, row.get(2)?, amount).map_err(|e| conversion(3, e))?,
+        reason: row.get::<_, String>(5)?.parse().map_err(|e| conversion(5, e))?
+    })
+}
+
+fn event_from_row(row: &Row) -> rusqlite::Result<Event> {
+    Event::new(&row.get::<_, String>(0)?, row.get(1)?, amount(row, 2)?)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into()))
+}
+
+impl SqliteStorage {
+    pub fn open<P: AsRef<Path>>(path: P, scale: Scale) -> StorageResult<Self> {
+        Self::with_connection(Connection::open(path)?, scale)
+    }
+
+    pub fn open_in_memory(scale: Scale) -> StorageResult<Self> {
+        Self::with_connection(Connection::open_in_memory()?, scale)
+    }
+
+    fn with_connection(mut connection: Connection, scale: Scale) -> StorageResult<Self> {
+        migrate(&mut connection)?;
+        let decimals = connection.query_row("SELECT value FROM meta WHERE key = 'decimals'", [], |row| row.get::<_, u32>(0)).optional()?;
+        match decimals {
+            Some(decimals) if decimals != scale.decimals() => return Err(StorageError::Incompatible(
+                format!("the amounts are stored with {} decimals, not {}", decimals, scale.decimals()))),
+            Some(_) => {},
+            None => {
+                connection.execute("INSERT INTO meta (key, value) VALUES ('decimals', ?1)", params![scale.decimals()])?;
+            }
+        }
+        let position = connection.query_row("SELECT value FROM meta WHERE key = 'position'", [], |row| row.get::<_, i64>(0))
+            .optional()?
+            .unwrap_or(0) as u64;
+        connection.execute_batch("BEGIN")?;
+        Ok(Self {
+            connection,
+            pending_writes: 0,
+            position,
+            committed: position
+        })
+    }
+
+    fn write(&mut self, sql: &str, params: impl rusqlite::Params) -> StorageResult<usize> {
+        match self.connection.prepare_cached(sql).and_then(|mut statement| statement.execute(params)) {
+            Ok(changed) => {
+                self.pending_writes += 1;
+                Ok(changed)
+            },
+            Err(e) => {
+                self.rollback();
+                Err(e.into())
+            }
+        }
+    }
+
+    // drops every write since the last commit, the row they belong to is not applied
+    fn rollback(&mut self) {
+        let _ = self.connection.execute_batch("ROLLBACK; BEGIN");
+        self.pending_writes = 0;
+        self.position = self.committed;
+    }
+
+    fn query_one<T, F: FnOnce(&Row) -> rusqlite::Result<T>>(&self, sql: &str, params: impl rusqlite::Params, f: F) -> StorageResult<Option<T>> {
+        Ok(self.connection.prepare_cached(sql)?.query_row(params, f).optional()?)
+    }
+
+    // the rows are read at once, a failure is yielded as the only item
+    fn query_all<T: 'static, F: FnMut(&Row) -> rusqlite::Result<T>>(&self, sql: &str, params: impl rusqlite::Params, f: F) -> Box<dyn Iterator<Item=StorageResult<T>>> {
+        let rows = self.connection.prepare_cached(sql)
+            .and_then(|mut statement| statement.query_map(params, f)?.collect::<rusqlite::Result<Vec<T>>>());
+        match rows {
+            Ok(rows) => Box::new(rows.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e.into())))
+        }
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn account(&self, cx: ClientID) -> StorageResult<Option<Account>> {
+        Ok(self.query_one("SELECT client, available, held, locked FROM accounts WHERE client = ?1", params![cx], account_from_row)?
+            .map(|(_, account)| account))
+    }
+
+    fn upsert_account(&mut self, cx: ClientID, account: &Account) -> StorageResult<()> {
+        self.write("INSERT OR REPLACE INTO accounts (client, available, held, locked) VALUES (?1, ?2, ?3, ?4)",
+                   params![cx, SqlAmount(account.available()), SqlAmount(account.held()), account.is_locked()])?;
+        Ok(())
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item=StorageResult<(ClientID, Account)>> + '_> {
+        self.query_all("SELECT client, available, held, locked FROM accounts", [], account_from_row)
+    }
+
+    fn transaction(&self, tx: TransactionID) -> StorageResult<Option<InnerTransaction>> {
+        Ok(self.query_one("SELECT tx, client, amount, disputed, withdrawal, stamp FROM transactions WHERE tx = ?1 AND expired = 0 AND reversed = 0",
+                          params![tx], transaction_from_row)?
+            .map(|(_, transaction)| transaction))
+    }
+
+    fn insert_transaction(&mut self, tx: TransactionID, transaction: InnerTransaction) -> StorageResult<()> {
+        self.write("INSERT OR REPLACE INTO transactions (tx, client, amount, disputed, withdrawal, stamp) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                   params![tx, transaction.client_id, SqlAmount(transaction.amount), SqlAmount(transaction.disputed), transaction.is_withdrawal,
+                           transaction.stamp as i64])?;
+        Ok(())
+    }
+
+    fn update_transaction(&mut self, tx: TransactionID, transaction: InnerTransaction) -> StorageResult<()> {
+        self.insert_transaction(tx, transaction)
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item=StorageResult<(TransactionID, InnerTransaction)>> + '_> {
+        self.query_all("SELECT tx, client, amount, disputed, withdrawal, stamp FROM transactions WHERE expired = 0 AND reversed = 0",
+                       [], transaction_from_row)
+    }
+
+    fn expire_transaction(&mut self, tx: TransactionID) -> StorageResult<()> {
+        self.write("UPDATE transactions SET expired = 1 WHERE tx = ?1", params![tx])?;
+        Ok(())
+    }
+
+    fn is_expired(&self, tx: TransactionID) -> StorageResult<bool> {
+        Ok(self.query_one("SELECT expired FROM transactions WHERE tx = ?1", params![tx], |row| row.get(0))?
+            .unwrap_or(false))
+    }
+
+    fn expire_before(&mut self, cutoff: u64) -> StorageResult<usize> {
+        self.write("UPDATE transactions SET expired = 1 WHERE expired = 0 AND reversed = 0 AND disputed = 0 AND stamp < ?1",
+                   params![cutoff as i64])
+    }
+
+    fn reverse_transaction(&mut self, tx: TransactionID) -> StorageResult<()> {
+        self.write("UPDATE transactions SET reversed = 1 WHERE tx = ?1 AND expired = 0", params![tx])?;
+        Ok(())
+    }
+
+    fn is_reversed(&self, tx: TransactionID) -> StorageResult<bool> {
+        Ok(self.query_one("SELECT reversed FROM transactions WHERE tx = ?1", params![tx], |row| row.get(0))?
+            .unwrap_or(false))
+    }
+
+    fn record(&mut self, cx: ClientID, event: Event) -> StorageResult<()> {
+        self.write("INSERT INTO events (client, event, tx, amount) VALUES (?1, ?2, ?3, ?4)",
+                   params![cx, event.to_string(), event.tx(), SqlAmount(event.amount().unwrap_or(0))])?;
+        Ok(())
+    }
+
+    fn events(&self, cx: ClientID) -> Box<dyn Iterator<Item=StorageResult<Event>> + '_> {
+        self.query_all("SELECT event, tx, amount FROM events WHERE client = ?1 ORDER BY id", params![cx], event_from_row)
+    }
+
+    fn flag(&self, cx: ClientID) -> StorageResult<Option<Flag>> {
+        self.query_one("SELECT reason, locked FROM flags WHERE client = ?1", params![cx], |row| Ok(Flag{reason: row.get(0)?, locked: row.get(1)?}))
+    }
+
+    fn set_flag(&mut self, cx: ClientID, flag: &Flag) -> StorageResult<()> {
+        self.write("INSERT OR REPLACE INTO flags (client, reason, locked) VALUES (?1, ?2, ?3)", params![cx, flag.reason, flag.locked])?;
+        Ok(())
+    }
+
+    fn interest_period(&self, cx: ClientID) -> StorageResult<Option<u64>> {
+        self.query_one("SELECT period FROM interest WHERE client = ?1", params![cx], |row| Ok(row.get::<_, i64>(0)? as u64))
+    }
+
+    fn set_interest_period(&mut self, cx: ClientID, period: u64) -> StorageResult<()> {
+        self.write("INSERT OR REPLACE INTO interest (client, period) VALUES (?1, ?2)", params![cx, period as i64])?;
+        Ok(())
+    }
+
+    fn record_rejection(&mut self, rejection: &Rejection) -> StorageResult<()> {
+        let rejected = &rejection.transaction;
+        self.write("INSERT INTO rejections (position, tx, client, type, amount, reason, line) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                   params![rejection.position as i64, rejected.tx(), rejected.client(), rejected.name(), rejected.amount().map(SqlAmount),
+                           rejection.reason.to_string(), rejection.line as i64])?;
+        Ok(())
+    }
+
+    fn rejections(&self) -> Box<dyn Iterator<Item=StorageResult<Rejection>> + '_> {
+        self.query_all("SELECT position, tx, client, type, amount, reason, line FROM rejections ORDER BY id", [], rejection_from_row)
+    }
+
+    fn open_dispute_case(&self, tx: TransactionID) -> StorageResult<Option<DisputeCase>> {
+        self.query_one("SELECT tx, client, opened, status, disputed, charged_back, reason_code, case_id, deadline FROM dispute_cases \
+                        WHERE tx = ?1 AND status = 'open'", params![tx], case_from_row)
+    }
+
+    fn upsert_dispute_case(&mut self, case: &DisputeCase) -> StorageResult<()> {
+        let params = params![case.tx, case.client_id, case.opened as i64, case.status.to_string(), SqlAmount(case.disputed),
+                             SqlAmount(case.charged_back), case.info.reason_code, case.info.case_id, case.info.deadline];
+        let updated = self.write("UPDATE dispute_cases SET client = ?2, opened = ?3, status = ?4, disputed = ?5, charged_back = ?6, \
+                                  reason_code = ?7, case_id = ?8, deadline = ?9 WHERE tx = ?1 AND status = 'open'", params)?;
+        if updated == 0 {
+            self.write("INSERT INTO dispute_cases (tx, client, opened, status, disputed, charged_back, reason_code, case_id, deadline) \
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)", params)?;
+        }
+        Ok(())
+    }
+
+    fn dispute_cases(&self) -> Box<dyn Iterator<Item=StorageResult<DisputeCase>> + '_> {
+        self.query_all("SELECT tx, client, opened, status, disputed, charged_back, reason_code, case_id, deadline FROM dispute_cases ORDER BY id",
+                       [], case_from_row)
+    }
+
+    fn position(&self) -> StorageResult<u64> {
+        Ok(self.position)
+    }
+
+    // the rows before `position` are complete, a commit here cannot split one
+    fn set_position(&mut self, position: u64) -> StorageResult<()> {
+        if self.pending_writes >= WRITES_PER_COMMIT {
+            self.flush()?;
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StorageResult<()> {
+        let result = self.connection.prepare_cached("INSERT OR REPLACE INTO meta (key, value) VALUES ('position', ?1)")
+            .and_then(|mut statement| statement.execute(params![self.position as i64]))
+            .and_then(|_| self.connection.execute_batch("COMMIT; BEGIN"));
+        if let Err(e) = result {
+            self.rollback();
+            return Err(e.into());
+        }
+        self.pending_writes = 0;
+        self.committed = self.position;
+        Ok(())
+    }
+}
+
+// a run that did not flush stopped partway through, its last writes are not kept
+impl Drop for SqliteStorage {
+    fn drop(&mut self) {
+        let _ = self.connection.execute_batch("ROLLBACK");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common_types::Transaction;
+    use crate::events::Event;
+    use crate::account::Account;
+    use crate::ledger::{Balance, Book, Posting};
+    use rusqlite::Connection;
+    use crate::common_types::{Amount, DisputeInfo, RejectReason, Rejection};
+    use crate::disputes::DisputeCase;
+    use crate::heuristics::Flag;
+    use crate::scale::Scale;
+    use crate::sqlite_storage::{migrate, schema_version, SqliteStorage, MIGRATIONS};
+    use crate::storage::{Storage, StorageError};
+    use crate::transaction_engine::{InnerTransaction, TransactionEngine};
+    use crate::verify::verify;
+
+    #[test]
+    fn accounts() {
+        let mut storage = SqliteStorage::open_in_memory(Scale::default()).unwrap();
+        let mut account = crate::account::Account::new();
+        account.deposit(1, 42).unwrap();
+        storage.upsert_account(1, &account).unwrap();
+        assert_eq!(storage.account(1).unwrap(), Some(account));
+        assert_eq!(storage.account(2).unwrap(), None);
+        assert_eq!(storage.accounts().count(), 1);
+    }
+
+    #[test]
+    fn transactions() {
+        let mut storage = SqliteStorage::open_in_memory(Scale::default()).unwrap();
+        let transaction = InnerTransaction{client_id: 1, disputed: 0, is_withdrawal: false, amount: 42, stamp: 1};
+        storage.insert_transaction(1, transaction).unwrap();
+        storage.insert_transaction(2, InnerTransaction{stamp: 5, ..transaction}).unwrap();
+        storage.update_transaction(1, InnerTransaction{disputed: 12, ..transaction}).unwrap();
+        assert_eq!(storage.transaction(1).unwrap().unwrap().disputed, 12);
+        assert_eq!(storage.expire_before(10).unwrap(), 1);
+        assert!(storage.is_expired(2).unwrap());
+        assert_eq!(storage.transaction(2).unwrap(), None);
+        storage.insert_transaction(3, InnerTransaction{is_withdrawal: true, stamp: 20, ..transaction}).unwrap();
+        assert!(storage.transaction(3).unwrap().unwrap().is_withdrawal);
+        storage.reverse_transaction(3).unwrap();
+        assert!(storage.is_reversed(3).unwrap());
+        assert_eq!(storage.transaction(3).unwrap(), None);
+        storage.expire_transaction(1).unwrap();
+        assert_eq!(storage.transactions().count(), 0);
+    }
+
+    #[test]
+    fn events() {
+        let mut storage = SqliteStorage::open_in_memory(Scale::default()).unwrap();
+        let events = vec![Event::Deposited(1, 42), Event::ChargedBack(1, 12), Event::Locked(2)];
+        for event in &events {
+            storage.record(1, *event).unwrap();
+        }
+        assert_eq!(storage.events(1).map(Result::unwrap).collect::<Vec<Event>>(), events);
+        assert_eq!(storage.postings(1).map(Result::unwrap).collect::<Vec<Posting>>(), vec![
+            Posting::new(1, Book::Settlement, Book::Available, 42),
+            Posting::new(1, Book::Held, Book::ChargebackLoss, 12)
+        ]);
+        assert_eq!(storage.events(2).count(), 0);
+    }
+
+    #[test]
+    fn flags() {
+        let mut storage = SqliteStorage::open_in_memory(Scale::default()).unwrap();
+        let flag = Flag{reason: "dispute_frequency:2/4".to_string(), locked: false};
+        storage.set_flag(1, &flag).unwrap();
+        assert_eq!(storage.flag(1).unwrap(), Some(flag));
+        storage.set_flag(1, &Flag{reason: "dispute_ratio:0.5".to_string(), locked: true}).unwrap();
+        assert!(storage.flag(1).unwrap().unwrap().locked);
+        assert_eq!(storage.flag(2).unwrap(), None);
+    }
+
+    #[test]
+    fn rejections() {
+        let mut storage = SqliteStorage::open_in_memory(Scale::default()).unwrap();
+        let rejections = vec![
+            Rejection{position: 2, line: 3, transaction: Transaction::Withdrawal(2, 1, 50), reason: RejectReason::InsufficientFunds},
+            Rejection{position: 3, line: 4, transaction: Transaction::Dispute(1, 2, None, None), reason: RejectReason::WrongClient},
+            Rejection{position: 5, line: 7, transaction: Transaction::Deposit(3, 1, 10), reason: RejectReason::LimitExceeded("max_deposit")}
+        ];
+        for rejection in &rejections {
+            storage.record_rejection(rejection).unwrap();
+        }
+        assert_eq!(storage.rejections().map(Result::unwrap).collect::<Vec<Rejection>>(), rejections);
+    }
+
+    #[test]
+    fn dispute_cases() {
+        let mut storage = SqliteStorage::open_in_memory(Scale::default()).unwrap();
+        let mut case = DisputeCase::open(1, 2, 5);
+        case.dispute(42, Some(&DisputeInfo{reason_code: Some("4837".to_string()), case_id: None, deadline: None}));
+        storage.upsert_dispute_case(&case).unwrap();
+        case.charged_back = 12;
+        case.close();
+        storage.upsert_dispute_case(&case).unwrap();
+        assert_eq!(storage.open_dispute_case(1).unwrap(), None);
+        let reopened = DisputeCase::open(1, 2, 9);
+        storage.upsert_dispute_case(&reopened).unwrap();
+        assert_eq!(storage.open_dispute_case(1).unwrap(), Some(reopened.clone()));
+        assert_eq!(storage.dispute_cases().map(Result::unwrap).collect::<Vec<DisputeCase>>(), vec![case, reopened]);
+    }
+
+    #[test]
+    fn engine_on_sqlite() {
+        let mut te = TransactionEngine::with_storage(Box::new(SqliteStorage::open_in_memory(Scale::default()).unwrap())).unwrap();
+        te.process_transaction(Transaction::Deposit(1, 1, 12));
+        te.process_transaction(Transaction::Deposit(2, 1, 30));
+        te.process_transaction(Transaction::Withdrawal(3, 1, 2));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Chargeback(1, 1, None));
+        let accounts: Vec<_> = te.get_accounts().map(Result::unwrap).collect();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].account.total(), 28);
+        assert!(accounts[0].account.is_locked());
+        assert_eq!(te.get_balance_at(1, 3).unwrap(), Some(Balance{available: 40, held: 0}));
+        assert_eq!(verify(&te).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn position_resumed() {
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        {
+            let mut te = TransactionEngine::with_storage(Box::new(SqliteStorage::open(&path, Scale::default()).unwrap())).unwrap();
+            te.process_transaction(Transaction::Deposit(1, 1, 12));
+            te.process_transaction(Transaction::Deposit(2, 1, 30));
+            te.flush().unwrap();
+        }
+        let storage = SqliteStorage::open(&path, Scale::default()).unwrap();
+        assert_eq!(storage.position().unwrap(), 2);
+        let mut te = TransactionEngine::with_storage(Box::new(storage)).unwrap();
+        te.process_transaction(Transaction::Deposit(3, 1, 1));
+        assert_eq!(te.get_transactions().map(|item| item.unwrap().1.stamp).max(), Some(3));
+    }
+
+    #[test]
+    fn partial_row_rolled_back() {
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        let account = |available| Account::restore(Balance{available, held: 0}, false);
+        {
+            let mut storage = SqliteStorage::open(&path, Scale::default()).unwrap();
+            storage.set_position(1).unwrap();
+            storage.upsert_account(1, &account(10)).unwrap();
+            storage.flush().unwrap();
+            storage.set_position(2).unwrap();
+            storage.upsert_account(1, &account(20)).unwrap();
+            // a failed write rolls back the row
+            storage.connection.execute_batch("DROP TABLE flags").unwrap();
+            assert!(storage.set_flag(1, &Flag{reason: "dispute_ratio:1.00".to_string(), locked: false}).is_err());
+            assert_eq!(storage.account(1).unwrap(), Some(account(10)));
+            assert_eq!(storage.position().unwrap(), 1);
+            storage.set_position(2).unwrap();
+            storage.upsert_account(1, &account(30)).unwrap();
+            // dropped partway through the row
+        }
+        let storage = SqliteStorage::open(&path, Scale::default()).unwrap();
+        assert_eq!(storage.account(1).unwrap(), Some(account(10)));
+        assert_eq!(storage.position().unwrap(), 1);
+    }
+
+    #[test]
+    fn migrations() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection).unwrap();
+        assert_eq!(schema_version(&connection).unwrap(), MIGRATIONS.len() as u32);
+        migrate(&mut connection).unwrap();
+        assert_eq!(schema_version(&connection).unwrap(), MIGRATIONS.len() as u32);
+    }
+
+    #[test]
+    fn refuse_newer_schema() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.pragma_update(None, "user_version", MIGRATIONS.len() as u32 + 1).unwrap();
+        assert!(matches!(SqliteStorage::with_connection(connection, Scale::default()), Err(StorageError::Incompatible(_))));
+    }
+
+    #[test]
+    fn wide_amounts() {
+        let mut storage = SqliteStorage::open_in_memory(Scale::default()).unwrap();
+        let transaction = InnerTransaction{client_id: 1, disputed: 0, is_withdrawal: false, amount: Amount::MAX, stamp: 1};
+        storage.insert_transaction(1, transaction).unwrap();
+        storage.record(1, Event::Deposited(1, Amount::MAX - 1)).unwrap();
+        assert_eq!(storage.transaction(1).unwrap(), Some(transaction));
+        assert_eq!(storage.events(1).map(Result::unwrap).collect::<Vec<Event>>(), vec![Event::Deposited(1, Amount::MAX - 1)]);
+    }
+
+    #[test]
+    fn refuse_other_scale() {
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        SqliteStorage::open(&path, Scale::new(2).unwrap()).unwrap();
+        assert!(SqliteStorage::open(&path, Scale::new(2).unwrap()).is_ok());
+        assert!(matches!(SqliteStorage::open(&path, Scale::default()), Err(StorageError::Incompatible(_))));
+    }
+}
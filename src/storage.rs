@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use crate::common_types::*;
+use crate::account::Account;
+use crate::disputes::{CaseStatus, DisputeCase};
+use crate::events::Event;
+use crate::heuristics::Flag;
+use crate::ledger::{Ledger, Posting};
+use crate::transaction_store::{InnerTransaction, TransactionStore};
+
+// a storage that could not be read or written, the engine stops processing on the first one
+#[derive(Debug)]
+pub enum StorageError {
+    Io(io::Error),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+    // stored data this version cannot use
+    Incompatible(String)
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::Io(e) => write!(f, "I/O error: {}", e),
+            #[cfg(feature = "sqlite")]
+            StorageError::Sqlite(e) => write!(f, "SQLite error: {}", e),
+            StorageError::Incompatible(reason) => write!(f, "incompatible storage: {}", reason)
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+// lets the outputs report a storage that failed while they were reading it
+impl From<StorageError> for io::Error {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::Io(e) => e,
+            e => io::Error::other(e)
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        StorageError::Io(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Sqlite(e)
+    }
+}
+
+// everything the engine persists: the accounts, the transactions that can still be disputed or reversed, the client events,
+// the dispute cases, the clients flagged by the fraud heuristics, the interest paid, the rejections and the position of the
+// last processed row
+pub trait Storage {
+    fn account(&self, cx: ClientID) -> StorageResult<Option<Account>>;
+    fn upsert_account(&mut self, cx: ClientID, account: &Account) -> StorageResult<()>;
+    fn accounts(&self) -> Box<dyn Iterator<Item=StorageResult<(ClientID, Account)>> + '_>;
+
+    fn transaction(&self, tx: TransactionID) -> StorageResult<Option<InnerTransaction>>;
+    fn insert_transaction(&mut self, tx: TransactionID, transaction: InnerTransaction) -> StorageResult<()>;
+    fn update_transaction(&mut self, tx: TransactionID, transaction: InnerTransaction) -> StorageResult<()>;
+    fn transactions(&self) -> Box<dyn Iterator<Item=StorageResult<(TransactionID, InnerTransaction)>> + '_>;
+
+    // expired transactions are forgotten but their ID stays reserved
+    fn expire_transaction(&mut self, tx: TransactionID) -> StorageResult<()>;
+    fn is_expired(&self, tx: TransactionID) -> StorageResult<bool>;
+    // expires every undisputed transaction stamped before `cutoff`, returns how many were expired
+    fn expire_before(&mut self, cutoff: u64) -> StorageResult<usize>;
+
+    // reversed transactions are forgotten, their ID stays reserved and cannot be reversed again
+    fn reverse_transaction(&mut self, tx: TransactionID) -> StorageResult<()>;
+    fn is_reversed(&self, tx: TransactionID) -> StorageResult<bool>;
+
+    fn record(&mut self, cx: ClientID, event: Event) -> StorageResult<()>;
+    fn events(&self, cx: ClientID) -> Box<dyn Iterator<Item=StorageResult<Event>> + '_>;
+
+    fn postings(&self, cx: ClientID) -> Box<dyn Iterator<Item=StorageResult<Posting>> + '_> {
+        Box::new(self.events(cx).filter_map(|event| event.map(|event| event.posting()).transpose()))
+    }
+
+    fn flag(&self, cx: ClientID) -> StorageResult<Option<Flag>>;
+    fn set_flag(&mut self, cx: ClientID, flag: &Flag) -> StorageResult<()>;
+
+    // the number of interest periods paid to a client, none before the engine saw it with interest enabled
+    fn interest_period(&self, cx: ClientID) -> StorageResult<Option<u64>>;
+    fn set_interest_period(&mut self, cx: ClientID, period: u64) -> StorageResult<()>;
+
+    fn record_rejection(&mut self, rejection: &Rejection) -> StorageResult<()>;
+    // every rejection in the order they were recorded
+    fn rejections(&self) -> Box<dyn Iterator<Item=StorageResult<Rejection>> + '_>;
+
+    // a deposit has at most one open case, a case written with another status is closed
+    fn open_dispute_case(&self, tx: TransactionID) -> StorageResult<Option<DisputeCase>>;
+    // updates the open case of the deposit, or adds the case if it has none
+    fn upsert_dispute_case(&mut self, case: &DisputeCase) -> StorageResult<()>;
+    // every case in the order they were opened
+    fn dispute_cases(&self) -> Box<dyn Iterator<Item=StorageResult<DisputeCase>> + '_>;
+
+    // number of rows processed so far, a storage resumed from disk carries on from there
+    fn position(&self) -> StorageResult<u64>;
+    fn set_position(&mut self, position: u64) -> StorageResult<()>;
+
+    // makes every pending write durable
+    fn flush(&mut self) -> StorageResult<()> {
+        Ok(())
+    }
+
+    // an independent copy of everything stored, none if the storage cannot be copied
+    fn try_clone(&self) -> StorageResult<Option<Box<dyn Storage>>> {
+        Ok(None)
+    }
+}
+
+pub struct MemoryStorage {
+    accounts: HashMap<ClientID, Account>,
+    transactions: TransactionStore,
+    ledger: Ledger,
+    flags: HashMap<ClientID, Flag>,
+    interest_periods: HashMap<ClientID, u64>,
+    rejections: Vec<Rejection>,
+    cases: Vec<DisputeCase>,
+    // index in `cases` of the open case of every disputed deposit
+    open_cases: HashMap<TransactionID, usize>,
+    position: u64
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::with_transaction_store(TransactionStore::new())
+    }
+
+    pub fn with_transaction_store(transactions: TransactionStore) -> Self {
+        Self {
+            accounts: HashMap::new(),
+            transactions,
+            ledger: Ledger::new(),
+            flags: HashMap::new(),
+            interest_periods: HashMap::new(),
+            rejections: Vec::new(),
+            cases: Vec::new(),
+            open_cases: HashMap::new(),
+            position: 0
+        }
+    }
+}
+
+// only the transaction store can fail, when its pages are spilled to disk
+impl Storage for MemoryStorage {
+    fn account(&self, cx: ClientID) -> StorageResult<Option<Account>> {
+        Ok(self.accounts.get(&cx).copied())
+    }
+
+    fn upsert_account(&mut self, cx: ClientID, account: &Account) -> StorageResult<()> {
+        self.accounts.insert(cx, *account);
+        Ok(())
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item=StorageResult<(ClientID, Account)>> + '_> {
+        Box::new(self.accounts.iter().map(|(k, v)| Ok((*k, *v))))
+    }
+
+    fn transaction(&self, tx: TransactionID) -> StorageResult<Option<InnerTransaction>> {
+        Ok(self.transactions.get(tx)?)
+    }
+
+    fn insert_transaction(&mut self, tx: TransactionID, transaction: InnerTransaction) -> StorageResult<()> {
+        Ok(self.transactions.insert(tx, transaction)?)
+    }
+
+    fn update_transaction(&mut self, tx: TransactionID, transaction: InnerTransaction) -> StorageResult<()> {
+        Ok(self.transactions.insert(tx, transaction)?)
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item=StorageResult<(TransactionID, InnerTransaction)>> + '_> {
+        Box::new(self.transactions.iter().map(|item| Ok(item?)))
+    }
+
+    fn expire_transaction(&mut self, tx: TransactionID) -> StorageResult<()> {
+        self.transactions.expire(tx)?;
+        Ok(())
+    }
+
+    fn is_expired(&self, tx: TransactionID) -> StorageResult<bool> {
+        Ok(self.transactions.is_expired(tx))
+    }
+
+    fn expire_before(&mut self, cutoff: u64) -> StorageResult<usize> {
+        Ok(self.transactions.expire_before(cutoff)?)
+    }
+
+    fn reverse_transaction(&mut self, tx: TransactionID) -> StorageResult<()> {
+        self.transactions.reverse(tx)?;
+        Ok(())
+    }
+
+    fn is_reversed(&self, tx: TransactionID) -> StorageResult<bool> {
+        Ok(self.transactions.is_reversed(tx))
+    }
+
+    fn record(&mut self, cx: ClientID, event: Event) -> StorageResult<()> {
+        self.ledger.record(cx, event);
+        Ok(())
+    }
+
+    fn events(&self, cx: ClientID) -> Box<dyn Iterator<Item=StorageResult<Event>> + '_> {
+        Box::new(self.ledger.events(cx).map(Ok))
+    }
+
+    fn flag(&self, cx: ClientID) -> StorageResult<Option<Flag>> {
+        Ok(self.flags.get(&cx).cloned())
+    }
+
+    fn set_flag(&mut self, cx: ClientID, flag: &Flag) -> StorageResult<()> {
+        self.flags.insert(cx, flag.clone());
+        Ok(())
+    }
+
+    fn interest_period(&self, cx: ClientID) -> StorageResult<Option<u64>> {
+        Ok(self.interest_periods.get(&cx).copied())
+    }
+
+    fn set_interest_period(&mut self, cx: ClientID, period: u64) -> StorageResult<()> {
+        self.interest_periods.insert(cx, period);
+        Ok(())
+    }
+
+    fn record_rejection(&mut self, rejection: &Rejection) -> StorageResult<()> {
+        self.rejections.push(rejection.clone());
+        Ok(())
+    }
+
+    fn rejections(&self) -> Box<dyn Iterator<Item=StorageResult<Rejection>> + '_> {
+        Box::new(self.rejections.iter().cloned().map(Ok))
+    }
+
+    fn open_dispute_case(&self, tx: TransactionID) -> StorageResult<Option<DisputeCase>> {
+        Ok(self.open_cases.get(&tx).map(|index| self.cases[*index].clone()))
+    }
+
+    fn upsert_dispute_case(&mut self, case: &DisputeCase) -> StorageResult<()> {
+        match self.open_cases.get(&case.tx) {
+            Some(index) => self.cases[*index] = case.clone(),
+            None => {
+                self.open_cases.insert(case.tx, self.cases.len());
+                self.cases.push(case.clone());
+            }
+        }
+        if case.status != CaseStatus::Open {
+            self.open_cases.remove(&case.tx);
+        }
+        Ok(())
+    }
+
+    fn dispute_cases(&self) -> Box<dyn Iterator<Item=StorageResult<DisputeCase>> + '_> {
+        Box::new(self.cases.iter().cloned().map(Ok))
+    }
+
+    fn position(&self) -> StorageResult<u64> {
+        Ok(self.position)
+    }
+
+    fn set_position(&mut self, position: u64) -> StorageResult<()> {
+        self.position = position;
+        Ok(())
+    }
+
+    fn try_clone(&self) -> StorageResult<Option<Box<dyn Storage>>> {
+        Ok(Some(Box::new(Self {
+            accounts: self.accounts.clone(),
+            transactions: self.transactions.try_clone()?,
+            ledger: self.ledger.clone(),
+            flags: self.flags.clone(),
+            interest_periods: self.interest_periods.clone(),
+            rejections: self.rejections.clone(),
+            cases: self.cases.clone(),
+            open_cases: self.open_cases.clone(),
+            position: self.position
+        })))
+    }
+}
@@ -1,141 +1,754 @@
-use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use crate::common_types::*;
 use crate::account::Account;
-use log::{warn, info};
+use crate::events::{self, Event};
+use crate::ledger::{self, Balance, Book, Posting, BOOKS};
+use crate::storage::{MemoryStorage, Storage, StorageError, StorageResult};
+use crate::rules::RiskRules;
+use crate::heuristics::Flag;
+use crate::limits::ClientLimits;
+use crate::fees::{FeeSchedule, InterestPolicy};
+use crate::disputes::{DisputeCase, OpenDispute};
+use log::{warn, info, error};
 
-struct InnerTransaction {
-    client_id: ClientID,
-    is_disputed: bool,
-    amount: Amount
+pub use crate::transaction_store::InnerTransaction;
+
+// how often expired transactions are pruned from the store by default, in rows
+pub const PRUNE_INTERVAL: u64 = 1024;
+// how often the risk rules file is checked for changes, in rows
+const RULES_RELOAD_INTERVAL: u64 = 1024;
+
+// how long a deposit can be disputed before it is evicted from the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionPolicy {
+    Rows(u64),
+    Seconds(u64)
+}
+
+// why a transaction was not applied: the engine refused it, or the storage failed while applying it
+enum Failure {
+    Rejected(RejectReason),
+    Storage(StorageError)
+}
+
+impl From<RejectReason> for Failure {
+    fn from(reason: RejectReason) -> Self {
+        Failure::Rejected(reason)
+    }
+}
+
+impl From<StorageError> for Failure {
+    fn from(e: StorageError) -> Self {
+        Failure::Storage(e)
+    }
+}
+
+// the current time in seconds, the wall clock unless the engine is given another one
+pub type Clock = Rc<dyn Fn() -> u64>;
+
+fn unix_seconds() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
 }
 
 pub struct TransactionEngine {
-    accounts: HashMap<ClientID, Account>,
-    transactions: HashMap<TransactionID, InnerTransaction>
+    storage: Box<dyn Storage>,
+    // the first storage failure, nothing is processed after it
+    failure: Option<StorageError>,
+    retention: Option<RetentionPolicy>,
+    prune_interval: u64,
+    // read for the seconds retention, the withdrawal limits window and the day of the risk rules
+    clock: Clock,
+    position: u64,
+    // input line of the transaction being processed
+    line: u64,
+    // the rejection of the transaction processed last, the storage keeps every rejection
+    last_rejection: Option<Rejection>,
+    rules: Option<RiskRules>,
+    limits: Option<ClientLimits>,
+    fees: FeeSchedule,
+    interest: Option<InterestPolicy>,
+    // withdrawals can be disputed like deposits
+    dispute_withdrawals: bool
+}
+
+impl Default for TransactionEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-pub struct ClientAccount<'a> {
+pub struct ClientAccount {
     pub client_id: ClientID,
-    pub account: &'a Account
+    pub account: Account,
+    // set when the fraud heuristics flagged the client
+    pub flag: Option<Flag>
+}
+
+// an account a what-if simulation would change, `before` is none for a new client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountChange {
+    pub client_id: ClientID,
+    pub before: Option<Account>,
+    pub after: Account
 }
 
 impl TransactionEngine {
     pub fn new() -> Self {
+        Self::with_position(Box::new(MemoryStorage::new()), 0)
+    }
+
+    // the engine carries on from the position saved in the storage
+    pub fn with_storage(storage: Box<dyn Storage>) -> StorageResult<Self> {
+        let position = storage.position()?;
+        Ok(Self::with_position(storage, position))
+    }
+
+    fn with_position(storage: Box<dyn Storage>, position: u64) -> Self {
         Self {
-            accounts: HashMap::new(),
-            transactions: HashMap::new()
+            storage,
+            failure: None,
+            retention: None,
+            prune_interval: PRUNE_INTERVAL,
+            clock: Rc::new(unix_seconds),
+            position,
+            line: 0,
+            last_rejection: None,
+            rules: None,
+            limits: None,
+            fees: FeeSchedule::default(),
+            interest: None,
+            dispute_withdrawals: false
+        }
+    }
+
+    // an independent copy of the engine, none if its storage cannot be copied or already failed
+    pub fn try_clone(&self) -> StorageResult<Option<Self>> {
+        if self.failure.is_some() {
+            return Ok(None);
         }
+        let Some(storage) = self.storage.try_clone()? else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            storage,
+            failure: None,
+            retention: self.retention,
+            prune_interval: self.prune_interval,
+            clock: self.clock.clone(),
+            position: self.position,
+            line: self.line,
+            last_rejection: self.last_rejection.clone(),
+            rules: self.rules.clone(),
+            limits: self.limits.clone(),
+            fees: self.fees,
+            interest: self.interest,
+            dispute_withdrawals: self.dispute_withdrawals
+        }))
+    }
+
+    // processes `candidate` on a copy of the engine and returns every account whose balances or lock would change,
+    // sorted by client. The engine itself is left untouched, it should be flushed first to compare accounts paid the
+    // interest due on both sides
+    pub fn what_if<I: IntoIterator<Item=Transaction>>(&self, candidate: I) -> StorageResult<Option<Vec<AccountChange>>> {
+        let Some(mut simulation) = self.try_clone()? else {
+            return Ok(None);
+        };
+        for transaction in candidate {
+            simulation.process_transaction(transaction);
+        }
+        if let Some(e) = simulation.failure.take() {
+            return Err(e);
+        }
+        simulation.flush()?;
+        let mut changes = Vec::new();
+        for item in simulation.storage.accounts() {
+            let (client_id, after) = item?;
+            let before = self.storage.account(client_id)?;
+            if before != Some(after) {
+                changes.push(AccountChange{client_id, before, after});
+            }
+        }
+        changes.sort_by_key(|change| change.client_id);
+        Ok(Some(changes))
+    }
+
+    pub fn set_retention_policy(&mut self, retention: Option<RetentionPolicy>) {
+        self.retention = retention;
+    }
+
+    // expired transactions are pruned every `prune_interval` rows, at least 1
+    pub fn set_prune_interval(&mut self, prune_interval: u64) {
+        self.prune_interval = prune_interval.max(1);
+    }
+
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    pub fn set_rules(&mut self, rules: Option<RiskRules>) {
+        self.rules = rules;
+    }
+
+    pub fn set_limits(&mut self, limits: Option<ClientLimits>) {
+        self.limits = limits;
+    }
+
+    pub fn set_fees(&mut self, fees: FeeSchedule) {
+        self.fees = fees;
     }
 
+    pub fn set_interest(&mut self, interest: Option<InterestPolicy>) {
+        self.interest = interest;
+    }
+
+    pub fn set_dispute_withdrawals(&mut self, dispute_withdrawals: bool) {
+        self.dispute_withdrawals = dispute_withdrawals;
+    }
+
+    // a transaction without an input line, the row count stands for it
     pub fn process_transaction(&mut self, transaction: Transaction) {
+        self.process_line(self.position + 1, transaction);
+    }
+
+    // processes the transaction read at `line` of the input, a dispute case records the line it was opened at.
+    // A storage failure stops the processing: it is kept in `failure` and every later transaction is ignored
+    pub fn process_line(&mut self, line: u64, transaction: Transaction) {
+        if self.failure.is_some() {
+            return;
+        }
+        self.line = line;
+        if let Err(e) = self.try_process_transaction(transaction) {
+            error!("Storage failure, stopping the processing: {}", e);
+            self.failure = Some(e);
+        }
+    }
+
+    fn try_process_transaction(&mut self, transaction: Transaction) -> StorageResult<()> {
         info!("Processing {:?}", transaction);
+        self.position += 1;
+        self.last_rejection = None;
+        self.storage.set_position(self.position)?;
+        self.settle_interest(transaction.client(), self.position - 1)?;
         use Transaction::*;
-        let tx_to_save = match transaction {
-            Deposit(tx, cx, amount) => {
-                if self.process_deposit(cx, amount) {
-                    Some((tx, InnerTransaction{
-                        client_id: cx,
-                        is_disputed: false,
-                        amount
-                    }))
-                } else {
-                    None
-                }
-            },
-            Withdrawal(_, cx, amount) => {
-                self.process_withdrawal(cx, amount);
-                None
-            },
-            Dispute(tx, cx) => {
-                self.process_dispute(tx, cx);
-                None
+        // the UTC day of the clock, the input has no timestamps
+        let day = if self.rules.is_some() { (self.clock)() / 86400 } else { 0 };
+        let result = self.check_rules(day, &transaction).map_err(Failure::from).and_then(|_| match transaction {
+            Deposit(tx, cx, amount) => self.process_deposit(tx, cx, amount),
+            Withdrawal(tx, cx, amount) => self.process_withdrawal(tx, cx, amount),
+            Dispute(tx, cx, amount, ref info) => self.process_dispute(tx, cx, amount, info.as_deref()),
+            Resolve(tx, cx, amount) => self.process_resolve(tx, cx, amount),
+            Chargeback(tx, cx, amount) => self.process_chargeback(tx, cx, amount),
+            Reversal(tx, cx) => self.process_reversal(tx, cx)
+        });
+        match result {
+            Ok(()) => if let Some(flag) = self.rules.as_mut().and_then(|rules| rules.record(self.position, day, &transaction)) {
+                self.flag(transaction.client(), transaction.tx(), flag)?;
             },
-            Resolve(tx, cx) => {
-                self.process_resolve(tx, cx);
-                None
+            Err(Failure::Rejected(reason)) => {
+                let rejection = Rejection{position: self.position, line: self.line, transaction, reason};
+                self.storage.record_rejection(&rejection)?;
+                self.last_rejection = Some(rejection);
             },
-            Chargeback(tx, cx) => {
-                self.process_chargeback(tx, cx);
-                None
+            Err(Failure::Storage(e)) => return Err(e)
+        }
+        if self.position % self.prune_interval == 0 {
+            self.prune()?;
+        }
+        if self.position % RULES_RELOAD_INTERVAL == 0 {
+            self.reload_rules();
+        }
+        Ok(())
+    }
+
+    // rows processed so far, including those of an earlier run resumed from the storage
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // the storage failure that stopped the processing, if any
+    pub fn failure(&self) -> Option<&StorageError> {
+        self.failure.as_ref()
+    }
+
+    // pays every account the interest due, then makes the pending writes durable
+    pub fn flush(&mut self) -> StorageResult<()> {
+        if self.interest.is_some() {
+            let clients = self.storage.accounts().map(|item| item.map(|(cx, _)| cx)).collect::<StorageResult<Vec<ClientID>>>()?;
+            for cx in clients {
+                self.settle_interest(cx, self.position)?;
             }
+        }
+        self.storage.flush()
+    }
+
+    pub fn get_accounts(&self) -> impl Iterator<Item=StorageResult<ClientAccount>> + '_ {
+        self.storage.accounts().map(|item| {
+            let (client_id, account) = item?;
+            Ok(ClientAccount{client_id, account, flag: self.storage.flag(client_id)?})
+        })
+    }
+
+    pub fn get_transactions(&self) -> impl Iterator<Item=StorageResult<(TransactionID, InnerTransaction)>> + '_ {
+        self.storage.transactions()
+    }
+
+    pub fn get_flag(&self, cx: ClientID) -> StorageResult<Option<Flag>> {
+        self.storage.flag(cx)
+    }
+
+    // every dispute case in the order they were opened
+    pub fn get_dispute_cases(&self) -> impl Iterator<Item=StorageResult<DisputeCase>> + '_ {
+        self.storage.dispute_cases()
+    }
+
+    // every deposit still under dispute, by transaction ID
+    pub fn get_open_disputes(&self) -> StorageResult<impl Iterator<Item=OpenDispute> + '_> {
+        let mut disputes = Vec::new();
+        for item in self.storage.transactions() {
+            let (tx, transaction) = item?;
+            if transaction.is_disputed() {
+                disputes.push(OpenDispute {
+                    tx,
+                    client_id: transaction.client_id,
+                    amount: transaction.disputed,
+                    // every open dispute has its case
+                    opened: self.storage.open_dispute_case(tx)?.map_or(0, |case| case.opened)
+                });
+            }
+        }
+        disputes.sort_by_key(|dispute| dispute.tx);
+        Ok(disputes.into_iter())
+    }
+
+    pub fn get_rejections(&self) -> impl Iterator<Item=StorageResult<Rejection>> + '_ {
+        self.storage.rejections()
+    }
+
+    // the rejection of the transaction processed last if it was refused, without reading it back from the storage
+    pub fn last_rejection(&self) -> Option<&Rejection> {
+        self.last_rejection.as_ref()
+    }
+
+    pub fn get_events(&self, cx: ClientID) -> impl Iterator<Item=StorageResult<Event>> + '_ {
+        self.storage.events(cx)
+    }
+
+    // every event of a client with the account state right after it
+    pub fn get_history(&self, cx: ClientID) -> StorageResult<impl Iterator<Item=(Event, Account)>> {
+        Ok(events::history(self.storage.events(cx).collect::<StorageResult<Vec<Event>>>()?))
+    }
+
+    // the account of a client rebuilt from its events alone
+    pub fn replay(&self, cx: ClientID) -> StorageResult<Account> {
+        Ok(Account::replay(self.storage.events(cx).collect::<StorageResult<Vec<Event>>>()?))
+    }
+
+    pub fn get_postings(&self, cx: ClientID) -> impl Iterator<Item=StorageResult<Posting>> + '_ {
+        self.storage.postings(cx)
+    }
+
+    pub fn get_statement(&self, cx: ClientID) -> StorageResult<impl Iterator<Item=(Posting, Balance)>> {
+        Ok(ledger::statement(self.storage.postings(cx).collect::<StorageResult<Vec<Posting>>>()?))
+    }
+
+    pub fn get_balance_at(&self, cx: ClientID, tx: TransactionID) -> StorageResult<Option<Balance>> {
+        Ok(ledger::balance_at(self.storage.postings(cx).collect::<StorageResult<Vec<Posting>>>()?, tx))
+    }
+
+    pub fn get_book_balance(&self, cx: ClientID, book: Book) -> StorageResult<SignedAmount> {
+        Ok(ledger::book_balance(&self.storage.postings(cx).collect::<StorageResult<Vec<Posting>>>()?, book))
+    }
+
+    // sum of every book of every client, money is neither created nor destroyed if this is zero
+    pub fn get_net_balance(&self) -> StorageResult<SignedAmount> {
+        let mut net: SignedAmount = 0;
+        for item in self.storage.accounts() {
+            let (cx, _) = item?;
+            for book in BOOKS {
+                net = net.wrapping_add(self.get_book_balance(cx, book)?);
+            }
+        }
+        Ok(net)
+    }
+
+    // the available and held books of every client must add up to its account, and the books of all clients must sum
+    // to zero
+    pub fn is_balanced(&self) -> StorageResult<bool> {
+        let mut net: SignedAmount = 0;
+        for item in self.storage.accounts() {
+            let (cx, account) = item?;
+            let postings = self.storage.postings(cx).collect::<StorageResult<Vec<Posting>>>()?;
+            let available = ledger::book_balance(&postings, Book::Available) as Amount;
+            let held = ledger::book_balance(&postings, Book::Held) as Amount;
+            if available != account.available() || held != account.held() || available.checked_add(held) != Some(account.total()) {
+                return Ok(false);
+            }
+            net = BOOKS.iter().fold(net, |net, &book| net.wrapping_add(ledger::book_balance(&postings, book)));
+        }
+        Ok(net == 0)
+    }
+
+    fn stamp(&self) -> u64 {
+        match self.retention {
+            Some(RetentionPolicy::Seconds(_)) => (self.clock)(),
+            _ => self.position
+        }
+    }
+
+    // transactions stamped before the cutoff are past their dispute window
+    fn cutoff(&self) -> Option<u64> {
+        self.retention.map(|retention| match retention {
+            RetentionPolicy::Rows(rows) => self.stamp().saturating_sub(rows),
+            RetentionPolicy::Seconds(seconds) => self.stamp().saturating_sub(seconds)
+        })
+    }
+
+    fn prune(&mut self) -> StorageResult<()> {
+        if let Some(cutoff) = self.cutoff() {
+            let expired = self.storage.expire_before(cutoff)?;
+            if expired > 0 {
+                info!("Evicted {} transactions past their dispute window", expired);
+            }
+        }
+        Ok(())
+    }
+
+    // expires the transaction if it is past its dispute window and was not pruned yet
+    fn check_expired(&mut self, tx: TransactionID) -> StorageResult<bool> {
+        if let (Some(transaction), Some(cutoff)) = (self.storage.transaction(tx)?, self.cutoff()) {
+            if !transaction.is_disputed() && transaction.stamp < cutoff {
+                self.storage.expire_transaction(tx)?;
+            }
+        }
+        self.storage.is_expired(tx)
+    }
+
+    fn check_rules(&self, day: u64, transaction: &Transaction) -> Result<(), RejectReason> {
+        if let Some(rules) = &self.rules {
+            rules.check(self.position, day, transaction).inspect_err(|reason| {
+                warn!("Transaction refused by the risk rules ({}), skipping cx={} tx={}", reason, transaction.client(), transaction.tx());
+            })?;
+        }
+        Ok(())
+    }
+
+    // a client keeps its first flag unless a later one locks it, `tx` is the transaction that tripped the heuristic
+    fn flag(&mut self, cx: ClientID, tx: TransactionID, flag: Flag) -> StorageResult<()> {
+        if self.storage.flag(cx)?.is_some_and(|current| current.locked || !flag.locked) {
+            return Ok(());
+        }
+        warn!("Client flagged by the fraud heuristics ({}), cx={}", flag.reason, cx);
+        if flag.locked {
+            if let Some(mut account) = self.storage.account(cx)?.filter(|account| !account.is_locked()) {
+                let event = account.lock(tx);
+                self.storage.upsert_account(cx, &account)?;
+                self.storage.record(cx, event)?;
+            }
+        }
+        self.storage.set_flag(cx, &flag)
+    }
+
+    fn reload_rules(&mut self) {
+        if let Some(rules) = &mut self.rules {
+            match rules.reload_if_changed() {
+                Ok(true) => info!("Reloaded the risk rules"),
+                Ok(false) => {},
+                Err(e) => error!("Failed to reload the risk rules, keeping the previous ones: {}", e)
+            }
+        }
+    }
+
+    // deposits and withdrawals must have unique transaction IDs, even once forgotten
+    fn is_used(&self, tx: TransactionID) -> StorageResult<bool> {
+        Ok(self.storage.transaction(tx)?.is_some() || self.storage.is_expired(tx)? || self.storage.is_reversed(tx)?)
+    }
+
+    // pays a client the interest periods completed within the first `rows` rows that it was not paid yet. The interest
+    // is paid lazily, before the next transaction of the client or on flush, an untouched account keeps its available
+    // funds so each period pays what it would have paid on time. A client starts from the period it is first seen in
+    fn settle_interest(&mut self, cx: ClientID, rows: u64) -> StorageResult<()> {
+        let Some(interest) = self.interest else {
+            return Ok(());
+        };
+        let due = rows / interest.period_rows;
+        let paid = self.storage.interest_period(cx)?;
+        if paid.is_some_and(|paid| paid >= due) {
+            return Ok(());
+        }
+        self.storage.set_interest_period(cx, due)?;
+        let Some(paid) = paid else {
+            return Ok(());
         };
-        if let Some((tx, transaction)) = tx_to_save {
-            self.transactions.insert(tx, transaction);
+        let Some(mut account) = self.storage.account(cx)?.filter(|account| !account.is_locked()) else {
+            return Ok(());
+        };
+        for _ in paid..due {
+            let amount = interest.rate.apply(account.available());
+            if amount == 0 {
+                break;
+            }
+            account = match self.update_account(cx, account, |account| account.receive_interest(amount)) {
+                Ok(account) => account,
+                Err(Failure::Storage(e)) => return Err(e),
+                Err(Failure::Rejected(_)) => break
+            };
         }
+        Ok(())
     }
 
-    pub fn get_accounts(&self) -> impl Iterator<Item=ClientAccount> {
-        self.accounts.iter().map(|(k, v)| ClientAccount{client_id: *k, account: v})
+    // runs an action on a client account, stores the result and records its event
+    fn update_account<Func: FnOnce(&mut Account) -> Result<Event, RejectReason>>(&mut self, cx: ClientID, account: Account, func: Func) -> Result<Account, Failure> {
+        let mut account = account;
+        let event = func(&mut account)?;
+        self.storage.upsert_account(cx, &account)?;
+        self.storage.record(cx, event)?;
+        Ok(account)
     }
 
-    fn process_deposit(&mut self, cx: ClientID, amount: Amount) -> bool {
-        if let Some(account) = self.accounts.get_mut(&cx) {
-            account.deposit(amount)
-        } else {
-            self.accounts.insert(cx, Account::new(amount));
-            true
+    fn process_deposit(&mut self, tx: TransactionID, cx: ClientID, amount: Amount) -> Result<(), Failure> {
+        if self.is_used(tx)? {
+            warn!("Deposit transaction type with an already used transaction ID, skipping cx={} tx={}", cx, tx);
+            return Err(RejectReason::DuplicateTransaction.into());
+        }
+        let account = self.storage.account(cx)?.unwrap_or_default();
+        if let Some(limits) = &self.limits {
+            limits.check_deposit(cx, account.total(), amount).inspect_err(|reason| {
+                warn!("Deposit transaction type over the client limits ({}), skipping cx={} tx={}", reason, cx, tx);
+            })?;
         }
+        self.update_account(cx, account, |account| account.deposit(tx, amount))?;
+        self.storage.insert_transaction(tx, InnerTransaction{
+            client_id: cx,
+            disputed: 0,
+            is_withdrawal: false,
+            amount,
+            stamp: self.stamp()
+        })?;
+        Ok(())
     }
 
-    fn process_withdrawal(&mut self, cx: ClientID, amount: Amount) -> bool {
-        if let Some(account) = self.accounts.get_mut(&cx) {
-            account.withdraw(amount)
-        } else {
+    fn process_withdrawal(&mut self, tx: TransactionID, cx: ClientID, amount: Amount) -> Result<(), Failure> {
+        let Some(account) = self.storage.account(cx)? else {
             warn!("Withdrawal transaction type on non-existing account, skipping cx={}", cx);
-            false
+            return Err(RejectReason::UnknownAccount.into());
+        };
+        if self.is_used(tx)? {
+            warn!("Withdrawal transaction type with an already used transaction ID, skipping cx={} tx={}", cx, tx);
+            return Err(RejectReason::DuplicateTransaction.into());
+        }
+        let now = if self.limits.is_some() { (self.clock)() } else { 0 };
+        if let Some(limits) = &mut self.limits {
+            limits.check_withdrawal(cx, self.position, now).inspect_err(|reason| {
+                warn!("Withdrawal transaction type over the client limits ({}), skipping cx={} tx={}", reason, cx, tx);
+            })?;
+        }
+        let fee = self.fees.withdrawal_fee(amount);
+        if fee > 0 && amount.saturating_add(fee) > account.available() && !account.is_locked() {
+            warn!("Withdrawal transaction type without enough funds for its fee, skipping cx={} tx={}", cx, tx);
+            return Err(RejectReason::InsufficientFunds.into());
+        }
+        let account = self.update_account(cx, account, |account| account.withdraw(tx, amount))?;
+        if fee > 0 {
+            self.update_account(cx, account, |account| account.charge_fee(tx, fee))?;
+        }
+        if let Some(limits) = &mut self.limits {
+            limits.record_withdrawal(cx, self.position, now);
+        }
+        self.storage.insert_transaction(tx, InnerTransaction{
+            client_id: cx,
+            disputed: 0,
+            is_withdrawal: true,
+            amount,
+            stamp: self.stamp()
+        })?;
+        Ok(())
+    }
+
+    fn process_dispute(&mut self, tx: TransactionID, cx: ClientID, amount: Option<Amount>, info: Option<&DisputeInfo>) -> Result<(), Failure> {
+        if self.check_expired(tx)? {
+            warn!("Dispute transaction type on an expired transaction, skipping cx={} tx={}", cx, tx);
+            return Err(RejectReason::ExpiredTransaction.into());
+        }
+        if self.storage.is_reversed(tx)? {
+            warn!("Dispute transaction type on a reversed transaction, skipping cx={} tx={}", cx, tx);
+            return Err(RejectReason::ReversedTransaction.into());
+        }
+        let transaction = self.storage.transaction(tx)?.ok_or(RejectReason::UnknownTransaction)?;
+        if transaction.is_withdrawal && !self.dispute_withdrawals {
+            warn!("Dispute transaction type on a withdrawal, skipping cx={} tx={}", cx, tx);
+            return Err(RejectReason::NotADeposit.into());
         }
+        // a deposit can be disputed in several parts as long as they do not add up to more than the deposit
+        let undisputed = transaction.amount - transaction.disputed;
+        if transaction.client_id != cx || transaction.is_disputed() && undisputed == 0 {
+            // wrong client ID or that transaction is already disputed
+            warn!("Dispute transaction type on wrong account or wrong transaction, skipping cx={} tx={}", cx, tx);
+            return Err(if transaction.client_id != cx { RejectReason::WrongClient } else { RejectReason::AlreadyDisputed }.into());
+        }
+        let amount = amount.unwrap_or(undisputed);
+        if amount == 0 || amount > undisputed {
+            warn!("Dispute transaction type for more than the undisputed amount, skipping cx={} tx={}", cx, tx);
+            return Err(if amount == 0 { RejectReason::InvalidAmount } else { RejectReason::ExceedsTransaction }.into());
+        }
+        let account = self.storage.account(cx)?.ok_or(RejectReason::UnknownAccount)?;
+        self.update_account(cx, account, |account| if transaction.is_withdrawal {
+            account.dispute_withdrawal(tx, amount)
+        } else {
+            account.dispute(tx, amount)
+        })?;
+        self.storage.update_transaction(tx, InnerTransaction{disputed: transaction.disputed + amount, ..transaction})?;
+        let mut case = self.storage.open_dispute_case(tx)?.unwrap_or_else(|| DisputeCase::open(tx, cx, self.line));
+        case.dispute(amount, info);
+        self.storage.upsert_dispute_case(&case)?;
+        Ok(())
     }
 
-    fn process_dispute(&mut self, tx: TransactionID, cx: ClientID) {
-        if let Some(transaction) = self.transactions.get_mut(&tx) {
-            if transaction.client_id != cx || transaction.is_disputed {
-                // wrong client ID or that transaction is already disputed
-                warn!("Dispute transaction type on wrong account or wrong transaction, skipping cx={} tx={}", cx, tx);
-                return;
+    // the transaction is forgotten once nothing is held by its disputes anymore
+    fn process_resolve_or_chargeback(&mut self, tx: TransactionID, cx: ClientID, amount: Option<Amount>, is_chargeback: bool) -> Result<(), Failure> {
+        if self.storage.is_expired(tx)? {
+            warn!("Resolve/Chargeback transaction type on an expired transaction, skipping cx={} tx={}", cx, tx);
+            return Err(RejectReason::ExpiredTransaction.into());
+        }
+        let transaction = self.storage.transaction(tx)?.ok_or(RejectReason::UnknownTransaction)?;
+        if transaction.client_id != cx || !transaction.is_disputed() {
+            // wrong client ID or that transaction is not disputed
+            warn!("Resolve/Chargeback transaction type on wrong account or wrong transaction, skipping cx={} tx={}", cx, tx);
+            return Err(if transaction.client_id != cx { RejectReason::WrongClient } else { RejectReason::NotDisputed }.into());
+        }
+        let amount = amount.unwrap_or(transaction.disputed);
+        if amount == 0 || amount > transaction.disputed {
+            warn!("Resolve/Chargeback transaction type for more than the disputed amount, skipping cx={} tx={}", cx, tx);
+            return Err(if amount == 0 { RejectReason::InvalidAmount } else { RejectReason::ExceedsDispute }.into());
+        }
+        let result = match self.storage.account(cx)? {
+            Some(account) => match self.update_account(cx, account, |account| match (transaction.is_withdrawal, is_chargeback) {
+                (false, true) => account.chargeback(tx, amount),
+                (false, false) => account.resolve(tx, amount),
+                (true, true) => account.chargeback_withdrawal(tx, amount),
+                (true, false) => account.resolve_withdrawal(tx, amount)
+            }) {
+                Ok(_) => Ok(()),
+                Err(Failure::Rejected(reason)) => Err(reason),
+                Err(Failure::Storage(e)) => return Err(e.into())
+            },
+            None => Err(RejectReason::UnknownAccount)
+        };
+        // a refused resolve or chargeback leaves the amount held, the transaction and its case stay disputed
+        result?;
+        let closed = amount == transaction.disputed;
+        if let Some(mut case) = self.storage.open_dispute_case(tx)? {
+            if is_chargeback {
+                case.charged_back += amount;
             }
-            if let Some(account) = self.accounts.get_mut(&cx) {
-                account.dispute(transaction.amount);
-                transaction.is_disputed = true;
+            if closed {
+                case.close();
             }
+            self.storage.upsert_dispute_case(&case)?;
         }
+        if !closed {
+            // a charged back part is gone from the deposit and cannot be disputed again
+            let charged_back = if is_chargeback { amount } else { 0 };
+            self.storage.update_transaction(tx, InnerTransaction{
+                disputed: transaction.disputed - amount,
+                amount: transaction.amount - charged_back,
+                ..transaction
+            })?;
+        } else {
+            // its ID stays reserved like the one of an expired transaction
+            self.storage.expire_transaction(tx)?;
+        }
+        Ok(())
     }
 
-    fn process_resolve_or_chargeback<Func: FnOnce(&mut Account, Amount)>(&mut self, tx: TransactionID, cx: ClientID, func: Func) {
-        if let Some(transaction) = self.transactions.get(&tx) {
-            if transaction.client_id != cx || !transaction.is_disputed {
-                // wrong client ID or that transaction is not disputed
-                warn!("Resolve/Chargeback transaction type on wrong account or wrong transaction, skipping cx={} tx={}", cx, tx);
-                return;
-            }
-            if let Some(account) = self.accounts.get_mut(&cx) {
-                func(account, transaction.amount);
+    fn process_resolve(&mut self, tx: TransactionID, cx: ClientID, amount: Option<Amount>) -> Result<(), Failure> {
+        self.process_resolve_or_chargeback(tx, cx, amount, false)
+    }
+
+    fn process_chargeback(&mut self, tx: TransactionID, cx: ClientID, amount: Option<Amount>) -> Result<(), Failure> {
+        self.process_resolve_or_chargeback(tx, cx, amount, true)?;
+        // the penalty takes what the available funds cover
+        if let Some(account) = self.storage.account(cx)? {
+            let fee = self.fees.chargeback.min(account.available());
+            if fee > 0 {
+                self.update_account(cx, account, |account| account.charge_fee(tx, fee))?;
             }
-            self.transactions.remove(&tx);
         }
+        Ok(())
     }
 
-    fn process_resolve(&mut self, tx: TransactionID, cx: ClientID) {
-        self.process_resolve_or_chargeback(tx, cx, |account, amount| {
-            account.resolve(amount);
-        });
+    // the fee charged with a withdrawal, recorded right after it, the schedule may have changed since
+    fn withdrawal_fee(&self, cx: ClientID, tx: TransactionID) -> StorageResult<Amount> {
+        let mut withdrew = false;
+        for event in self.storage.events(cx) {
+            match event? {
+                Event::Withdrew(id, _) if id == tx => withdrew = true,
+                Event::FeeCharged(id, fee) if withdrew && id == tx => return Ok(fee),
+                _ if withdrew => return Ok(0),
+                _ => {}
+            }
+        }
+        Ok(0)
     }
 
-    fn process_chargeback(&mut self, tx: TransactionID, cx: ClientID) {
-        self.process_resolve_or_chargeback(tx, cx, |account, amount| {
-            account.chargeback(amount);
-        });
+    fn process_reversal(&mut self, tx: TransactionID, cx: ClientID) -> Result<(), Failure> {
+        if self.check_expired(tx)? {
+            warn!("Reversal transaction type on an expired transaction, skipping cx={} tx={}", cx, tx);
+            return Err(RejectReason::ExpiredTransaction.into());
+        }
+        if self.storage.is_reversed(tx)? {
+            warn!("Reversal transaction type on an already reversed transaction, skipping cx={} tx={}", cx, tx);
+            return Err(RejectReason::ReversedTransaction.into());
+        }
+        let transaction = self.storage.transaction(tx)?.ok_or(RejectReason::UnknownTransaction)?;
+        if transaction.client_id != cx || transaction.is_disputed() {
+            // wrong client ID or the funds are held by a dispute
+            warn!("Reversal transaction type on wrong account or disputed transaction, skipping cx={} tx={}", cx, tx);
+            return Err(if transaction.client_id != cx { RejectReason::WrongClient } else { RejectReason::AlreadyDisputed }.into());
+        }
+        let account = self.storage.account(cx)?.ok_or(RejectReason::UnknownAccount)?;
+        if transaction.is_withdrawal {
+            let fee = self.withdrawal_fee(cx, tx)?;
+            let account = self.update_account(cx, account, |account| account.reverse_withdrawal(tx, transaction.amount))?;
+            if fee > 0 {
+                self.update_account(cx, account, |account| account.refund_fee(tx, fee))?;
+            }
+        } else {
+            self.update_account(cx, account, |account| account.reverse_deposit(tx, transaction.amount))?;
+        }
+        self.storage.reverse_transaction(tx)?;
+        Ok(())
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
-    use crate::transaction_engine::{ClientAccount, Transaction, TransactionEngine};
+    use crate::common_types::{Amount, ClientID, DisputeInfo, RejectReason, Rejection};
+    use crate::transaction_engine::{ClientAccount, RetentionPolicy, Transaction, TransactionEngine};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use crate::ledger::{Balance, Book, EntryKind};
+    use crate::rules::{MaxDailyWithdrawals, MaxWithdrawal, RiskRules, RulesConfig};
+    use crate::scale::Scale;
+    use crate::heuristics::{DisputeFrequency, DisputeRatio, Flag, HeuristicAction};
+    use crate::verify::verify;
+    use crate::limits::{ClientLimits, Limits, Window};
+    use crate::fees::{FeeSchedule, InterestPolicy};
+    use crate::disputes::{CaseStatus, DisputeCase, OpenDispute};
+    use crate::events::Event;
+    use crate::account::Account;
+    use crate::common_types::TransactionID;
+    use crate::storage::{MemoryStorage, Storage, StorageResult};
+    use crate::transaction_engine::InnerTransaction;
+    use std::collections::HashMap;
+    use std::io;
 
     #[test]
     fn test_deposit_no_account() {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 42));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].client_id, 1);
         assert_eq!(accounts[0].account.available(), 42);
@@ -146,17 +759,57 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 42));
         te.process_transaction(Transaction::Deposit(2, 1, 42));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].client_id, 1);
         assert_eq!(accounts[0].account.available(), 84);
     }
 
+    #[test]
+    fn test_deposit_duplicate_tx() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 42));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Deposit(1, 1, 10));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
+        assert_eq!(accounts[0].account.total(), 42);
+        assert_eq!(accounts[0].account.held(), 42);
+        // the dispute on the first deposit is left as it was
+        assert_eq!(te.get_rejections().map(Result::unwrap).map(|r| r.reason.clone()).collect::<Vec<RejectReason>>(), vec![RejectReason::DuplicateTransaction]);
+        assert_eq!(te.get_open_disputes().unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_deposit_overflow() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 9_000_000_000_000_000_000));
+        te.process_transaction(Transaction::Deposit(2, 1, 9_000_000_000_000_000_000));
+        te.process_transaction(Transaction::Deposit(3, 1, Amount::MAX));
+        let account = te.get_accounts().map(Result::unwrap).next().unwrap().account;
+        assert_eq!(account.total(), 18_000_000_000_000_000_000);
+        let reasons: Vec<RejectReason> = te.get_rejections().map(Result::unwrap).map(|rejection| rejection.reason.clone()).collect();
+        assert_eq!(reasons, vec![RejectReason::Overflow]);
+        assert_eq!(te.get_transactions().count(), 2);
+        assert!(verify(&te).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_deposit_duplicate_after_chargeback() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 10));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Chargeback(1, 1, None));
+        te.process_transaction(Transaction::Deposit(1, 2, 5));
+        assert_eq!(te.get_rejections().map(Result::unwrap).map(|r| r.reason.clone()).collect::<Vec<RejectReason>>(), vec![RejectReason::DuplicateTransaction]);
+        // the second client's account is never opened
+        assert_eq!(te.get_accounts().count(), 1);
+    }
+
     #[test]
     fn test_withdrawal_no_account() {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Withdrawal(1, 1, 42));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 0);
     }
 
@@ -165,7 +818,7 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 42));
         te.process_transaction(Transaction::Withdrawal(2, 1, 30));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.total(), 12);
     }
@@ -174,8 +827,8 @@ mod tests {
     fn test_dispute_normal() {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 42));
-        te.process_transaction(Transaction::Dispute(1, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 42);
         assert_eq!(accounts[0].account.available(), 0);
@@ -186,8 +839,8 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Dispute(1, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 12);
         assert_eq!(accounts[0].account.available(), 30);
@@ -198,9 +851,9 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Dispute(2, 1));
-        te.process_transaction(Transaction::Dispute(2, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 30);
         assert_eq!(accounts[0].account.available(), 12);
@@ -211,8 +864,8 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Dispute(3, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Dispute(3, 1, None, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 42);
@@ -223,8 +876,8 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Dispute(1, 2));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Dispute(1, 2, None, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 42);
@@ -234,9 +887,9 @@ mod tests {
     fn test_resolve_normal() {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 42));
-        te.process_transaction(Transaction::Dispute(1, 1));
-        te.process_transaction(Transaction::Resolve(1, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Resolve(1, 1, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 42);
@@ -247,9 +900,9 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Dispute(1, 1));
-        te.process_transaction(Transaction::Resolve(1, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Resolve(1, 1, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 42);
@@ -260,8 +913,8 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Resolve(3, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Resolve(3, 1, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 42);
@@ -272,8 +925,8 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Resolve(1, 2));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Resolve(1, 2, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 42);
@@ -284,8 +937,8 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Resolve(1, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Resolve(1, 1, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 42);
@@ -296,10 +949,10 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Dispute(1, 1));
-        te.process_transaction(Transaction::Resolve(1, 1));
-        te.process_transaction(Transaction::Resolve(1, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Resolve(1, 1, None));
+        te.process_transaction(Transaction::Resolve(1, 1, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 42);
@@ -309,9 +962,9 @@ mod tests {
     fn test_chargeback_normal() {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 42));
-        te.process_transaction(Transaction::Dispute(1, 1));
-        te.process_transaction(Transaction::Chargeback(1, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Chargeback(1, 1, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 0);
@@ -323,9 +976,9 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Dispute(1, 1));
-        te.process_transaction(Transaction::Chargeback(1, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Chargeback(1, 1, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 30);
@@ -336,8 +989,8 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Chargeback(3, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Chargeback(3, 1, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 42);
@@ -348,8 +1001,8 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Chargeback(1, 2));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Chargeback(1, 2, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 42);
@@ -360,10 +1013,626 @@ mod tests {
         let mut te = TransactionEngine::new();
         te.process_transaction(Transaction::Deposit(1, 1, 12));
         te.process_transaction(Transaction::Deposit(2, 1, 30));
-        te.process_transaction(Transaction::Chargeback(1, 1));
-        let accounts: Vec<ClientAccount> = te.get_accounts().collect();
+        te.process_transaction(Transaction::Chargeback(1, 1, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
         assert_eq!(accounts.len(), 1);
         assert_eq!(accounts[0].account.held(), 0);
         assert_eq!(accounts[0].account.available(), 42);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_statement() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 42));
+        te.process_transaction(Transaction::Withdrawal(2, 1, 50));
+        te.process_transaction(Transaction::Deposit(3, 1, 8));
+        te.process_transaction(Transaction::Withdrawal(4, 1, 2));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Chargeback(1, 1, None));
+        let statement: Vec<(EntryKind, Balance)> = te.get_statement(1).unwrap().map(|(posting, balance)| (posting.kind(), balance)).collect();
+        assert_eq!(statement, vec![
+            (EntryKind::Deposit, Balance{available: 42, held: 0}),
+            (EntryKind::Deposit, Balance{available: 50, held: 0}),
+            (EntryKind::Withdrawal, Balance{available: 48, held: 0}),
+            (EntryKind::Dispute, Balance{available: 6, held: 42}),
+            (EntryKind::Chargeback, Balance{available: 6, held: 0})
+        ]);
+    }
+
+    #[test]
+    fn test_balance_at() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 12));
+        te.process_transaction(Transaction::Deposit(2, 1, 30));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Resolve(1, 1, None));
+        assert_eq!(te.get_balance_at(1, 2).unwrap(), Some(Balance{available: 42, held: 0}));
+        assert_eq!(te.get_balance_at(1, 1).unwrap(), Some(Balance{available: 42, held: 0}));
+        assert_eq!(te.get_balance_at(1, 3).unwrap(), None);
+    }
+
+    #[test]
+    fn test_ledger_balanced() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 12));
+        te.process_transaction(Transaction::Deposit(2, 2, 30));
+        te.process_transaction(Transaction::Withdrawal(3, 2, 10));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Chargeback(1, 1, None));
+        te.process_transaction(Transaction::Dispute(2, 2, None, None));
+        assert!(te.is_balanced().unwrap());
+        assert_eq!(te.get_book_balance(1, Book::ChargebackLoss).unwrap(), 12);
+        assert_eq!(te.get_book_balance(2, Book::Settlement).unwrap(), -20);
+    }
+
+    #[test]
+    fn test_ledger_unbalanced() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 12));
+        te.process_transaction(Transaction::Dispute(1, 1, Some(2), None));
+        // the account no longer matches its postings
+        te.storage.upsert_account(1, &Account::restore(Balance{available: 10, held: 3}, false)).unwrap();
+        assert!(!te.is_balanced().unwrap());
+        te.storage.upsert_account(1, &Account::restore(Balance{available: 10, held: 2}, false)).unwrap();
+        assert!(te.is_balanced().unwrap());
+    }
+
+    #[test]
+    fn test_dispute_expired() {
+        let mut te = TransactionEngine::new();
+        te.set_retention_policy(Some(RetentionPolicy::Rows(2)));
+        te.process_transaction(Transaction::Deposit(1, 1, 12));
+        te.process_transaction(Transaction::Deposit(2, 1, 30));
+        te.process_transaction(Transaction::Deposit(3, 1, 5));
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Deposit(1, 1, 5));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
+        assert_eq!(accounts[0].account.held(), 30);
+        assert_eq!(accounts[0].account.total(), 47);
+        assert!(te.storage.is_expired(1).unwrap());
+    }
+
+    #[test]
+    fn test_dispute_expired_seconds() {
+        let now = Rc::new(Cell::new(1000));
+        let mut te = TransactionEngine::new();
+        te.set_retention_policy(Some(RetentionPolicy::Seconds(60)));
+        te.set_prune_interval(1);
+        let clock = now.clone();
+        te.set_clock(Rc::new(move || clock.get()));
+        te.process_transaction(Transaction::Deposit(1, 1, 12));
+        now.set(1030);
+        te.process_transaction(Transaction::Deposit(2, 1, 30));
+        now.set(1070);
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        assert!(te.storage.is_expired(1).unwrap());
+        assert!(!te.storage.is_expired(2).unwrap());
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        assert_eq!(te.get_rejections().map(Result::unwrap).last().unwrap().reason, RejectReason::ExpiredTransaction);
+    }
+
+    #[test]
+    fn test_daily_withdrawals_clock() {
+        // a minute before midnight UTC
+        let now = Rc::new(Cell::new(86400 - 60));
+        let mut te = TransactionEngine::new();
+        te.set_rules(Some(RiskRules::new(RulesConfig{max_daily_withdrawals: Some(MaxDailyWithdrawals{count: 1}), ..Default::default()}, Scale::default()).unwrap()));
+        let clock = now.clone();
+        te.set_clock(Rc::new(move || clock.get()));
+        te.process_transaction(Transaction::Deposit(1, 1, 100));
+        te.process_transaction(Transaction::Withdrawal(2, 1, 10));
+        te.process_transaction(Transaction::Withdrawal(3, 1, 10));
+        // the day is the one of the clock when the row is processed, not of the input
+        now.set(86400);
+        te.process_transaction(Transaction::Withdrawal(4, 1, 10));
+        let rejections: Vec<(u32, RejectReason)> = te.get_rejections().map(Result::unwrap).map(|rejection| (rejection.transaction.tx(), rejection.reason)).collect();
+        assert_eq!(rejections, vec![(3, RejectReason::Rule("max_daily_withdrawals"))]);
+    }
+
+    #[test]
+    fn test_prune_interval() {
+        let mut te = TransactionEngine::new();
+        te.set_retention_policy(Some(RetentionPolicy::Rows(1)));
+        te.set_prune_interval(4);
+        for tx in 1..4 {
+            te.process_transaction(Transaction::Deposit(tx, 1, 10));
+        }
+        assert!(!te.storage.is_expired(1).unwrap());
+        te.process_transaction(Transaction::Deposit(4, 1, 10));
+        assert!(te.storage.is_expired(2).unwrap());
+        assert!(!te.storage.is_expired(3).unwrap());
+    }
+
+    #[test]
+    fn test_prune_keeps_disputed() {
+        let mut te = TransactionEngine::new();
+        te.set_retention_policy(Some(RetentionPolicy::Rows(10)));
+        te.process_transaction(Transaction::Deposit(1, 1, 12));
+        te.process_transaction(Transaction::Deposit(2, 1, 30));
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        for tx in 3..2048 {
+            te.process_transaction(Transaction::Withdrawal(tx, 2, 1));
+        }
+        assert!(te.storage.is_expired(1).unwrap());
+        te.process_transaction(Transaction::Chargeback(2, 1, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
+        assert_eq!(accounts[0].account.total(), 12);
+        assert!(accounts[0].account.is_locked());
+    }
+
+    #[test]
+    fn test_rejections() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 42));
+        te.process_transaction(Transaction::Deposit(1, 1, 10));
+        te.process_transaction(Transaction::Withdrawal(2, 2, 5));
+        te.process_transaction(Transaction::Withdrawal(3, 1, 50));
+        te.process_transaction(Transaction::Dispute(1, 2, None, None));
+        te.process_transaction(Transaction::Resolve(1, 1, None));
+        te.process_transaction(Transaction::Dispute(4, 1, None, None));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        let rejections: Vec<Rejection> = te.get_rejections().map(Result::unwrap).collect();
+        assert_eq!(rejections.iter().map(|r| r.reason.clone()).collect::<Vec<RejectReason>>(), vec![
+            RejectReason::DuplicateTransaction,
+            RejectReason::UnknownAccount,
+            RejectReason::InsufficientFunds,
+            RejectReason::WrongClient,
+            RejectReason::NotDisputed,
+            RejectReason::UnknownTransaction,
+            RejectReason::AlreadyDisputed
+        ]);
+        assert_eq!(rejections[0], Rejection{position: 2, line: 2, transaction: Transaction::Deposit(1, 1, 10), reason: RejectReason::DuplicateTransaction});
+    }
+
+    #[test]
+    fn test_rejection_line() {
+        let mut te = TransactionEngine::new();
+        te.process_line(2, Transaction::Deposit(1, 1, 10));
+        assert_eq!(te.last_rejection(), None);
+        // line 3 could not be parsed
+        te.process_line(4, Transaction::Withdrawal(2, 1, 20));
+        let rejection = Rejection{position: 2, line: 4, transaction: Transaction::Withdrawal(2, 1, 20), reason: RejectReason::InsufficientFunds};
+        assert_eq!(te.last_rejection(), Some(&rejection));
+        assert_eq!(te.get_rejections().map(Result::unwrap).collect::<Vec<Rejection>>(), vec![rejection]);
+        te.process_line(5, Transaction::Withdrawal(3, 1, 5));
+        assert_eq!(te.last_rejection(), None);
+    }
+
+    #[test]
+    fn test_risk_rules() {
+        let mut te = TransactionEngine::new();
+        te.set_rules(Some(RiskRules::new(RulesConfig{max_withdrawal: Some(MaxWithdrawal{amount: "0.001".parse().unwrap()}), ..Default::default()}, Scale::default()).unwrap()));
+        te.process_transaction(Transaction::Deposit(1, 1, 42));
+        te.process_transaction(Transaction::Withdrawal(2, 1, 12));
+        te.process_transaction(Transaction::Withdrawal(3, 1, 10));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
+        assert_eq!(accounts[0].account.total(), 32);
+        let rejections: Vec<Rejection> = te.get_rejections().map(Result::unwrap).collect();
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].reason, RejectReason::Rule("max_withdrawal"));
+        assert_eq!(rejections[0].reason.to_string(), "rule:max_withdrawal");
+    }
+
+    #[test]
+    fn test_dispute_frequency_lock() {
+        let mut te = TransactionEngine::new();
+        let heuristic = DisputeFrequency{disputes: 2, transactions: 4, action: HeuristicAction::Lock};
+        te.set_rules(Some(RiskRules::new(RulesConfig{dispute_frequency: Some(heuristic), ..Default::default()}, Scale::default()).unwrap()));
+        te.process_transaction(Transaction::Deposit(1, 1, 10));
+        te.process_transaction(Transaction::Deposit(2, 1, 10));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Resolve(1, 1, None));
+        assert_eq!(te.get_flag(1).unwrap(), None);
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
+        assert!(accounts[0].account.is_locked());
+        assert_eq!(accounts[0].flag.as_ref().map(|flag| flag.reason.as_str()), Some("dispute_frequency:2/4"));
+        assert_eq!(te.get_events(1).last().unwrap().unwrap(), Event::Locked(2));
+        assert_eq!(te.replay(1).unwrap(), accounts[0].account);
+        assert_eq!(verify(&te).unwrap(), vec![]);
+        te.process_transaction(Transaction::Deposit(3, 1, 10));
+        assert_eq!(te.get_rejections().map(Result::unwrap).last().map(|rejection| rejection.reason.clone()), Some(RejectReason::LockedAccount));
+    }
+
+    #[test]
+    fn test_dispute_ratio_flag_kept() {
+        let mut te = TransactionEngine::new();
+        let heuristic = DisputeRatio{ratio: 0.5, min_deposits: 2, action: HeuristicAction::Flag};
+        te.set_rules(Some(RiskRules::new(RulesConfig{dispute_ratio: Some(heuristic), ..Default::default()}, Scale::default()).unwrap()));
+        te.process_transaction(Transaction::Deposit(1, 1, 10));
+        te.process_transaction(Transaction::Deposit(2, 1, 10));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        let flagged = Some(Flag{reason: "dispute_ratio:1.00".to_string(), locked: false});
+        assert_eq!(te.get_flag(1).unwrap(), flagged);
+        // the next dispute is checked at 3/6, no longer above the ratio, the client stays flagged
+        te.process_transaction(Transaction::Deposit(3, 1, 10));
+        te.process_transaction(Transaction::Deposit(4, 1, 10));
+        te.process_transaction(Transaction::Deposit(5, 1, 10));
+        te.process_transaction(Transaction::Deposit(6, 1, 10));
+        te.process_transaction(Transaction::Dispute(3, 1, None, None));
+        assert_eq!(te.get_flag(1).unwrap(), flagged);
+    }
+
+    #[test]
+    fn test_client_limits() {
+        let mut te = TransactionEngine::new();
+        let defaults = Limits{max_balance: Some(100), max_withdrawals: Some(1), window: Some(Window::Rows(10)), ..Default::default()};
+        te.set_limits(Some(ClientLimits::new(defaults, HashMap::from([(2, Limits{max_balance: Some(1000), ..Default::default()})]))));
+        te.process_transaction(Transaction::Deposit(1, 1, 60));
+        te.process_transaction(Transaction::Deposit(2, 1, 60));
+        te.process_transaction(Transaction::Deposit(3, 2, 600));
+        te.process_transaction(Transaction::Withdrawal(4, 1, 10));
+        te.process_transaction(Transaction::Withdrawal(5, 1, 10));
+        let reasons: Vec<RejectReason> = te.get_rejections().map(Result::unwrap).map(|rejection| rejection.reason.clone()).collect();
+        assert_eq!(reasons, vec![RejectReason::LimitExceeded("max_balance"), RejectReason::LimitExceeded("max_withdrawals")]);
+        let mut accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
+        accounts.sort_by_key(|account| account.client_id);
+        assert_eq!(accounts[0].account.total(), 50);
+        assert_eq!(accounts[1].account.total(), 600);
+    }
+
+    #[test]
+    fn test_fees() {
+        let mut te = TransactionEngine::new();
+        te.set_fees(FeeSchedule{withdrawal_flat: 1, withdrawal_rate: Some("0.1".parse().unwrap()), chargeback: 50});
+        te.process_transaction(Transaction::Deposit(1, 1, 100));
+        te.process_transaction(Transaction::Deposit(2, 1, 20));
+        te.process_transaction(Transaction::Withdrawal(3, 1, 10));
+        te.process_transaction(Transaction::Withdrawal(4, 1, 100));
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        te.process_transaction(Transaction::Chargeback(2, 1, None));
+        let statement: Vec<(EntryKind, u32, Balance)> = te.get_statement(1).unwrap().map(|(posting, balance)| (posting.kind(), posting.tx.unwrap(), balance)).collect();
+        assert_eq!(statement, vec![
+            (EntryKind::Deposit, 1, Balance{available: 100, held: 0}),
+            (EntryKind::Deposit, 2, Balance{available: 120, held: 0}),
+            (EntryKind::Withdrawal, 3, Balance{available: 110, held: 0}),
+            (EntryKind::Fee, 3, Balance{available: 108, held: 0}),
+            (EntryKind::Dispute, 2, Balance{available: 88, held: 20}),
+            (EntryKind::Chargeback, 2, Balance{available: 88, held: 0}),
+            (EntryKind::Fee, 2, Balance{available: 38, held: 0})
+        ]);
+        assert_eq!(te.get_book_balance(1, Book::House).unwrap(), 52);
+        assert!(te.is_balanced().unwrap());
+        assert_eq!(verify(&te).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_reversal_refunds_fee() {
+        let mut te = TransactionEngine::new();
+        te.set_fees(FeeSchedule{withdrawal_flat: 2, withdrawal_rate: None, chargeback: 0});
+        te.process_transaction(Transaction::Deposit(1, 1, 100));
+        te.process_transaction(Transaction::Withdrawal(2, 1, 10));
+        // the refund is the fee that was charged, not the one of the current schedule
+        te.set_fees(FeeSchedule{withdrawal_flat: 5, withdrawal_rate: None, chargeback: 0});
+        te.process_transaction(Transaction::Reversal(2, 1));
+        let statement: Vec<(EntryKind, Balance)> = te.get_statement(1).unwrap().map(|(posting, balance)| (posting.kind(), balance)).collect();
+        assert_eq!(statement, vec![
+            (EntryKind::Deposit, Balance{available: 100, held: 0}),
+            (EntryKind::Withdrawal, Balance{available: 90, held: 0}),
+            (EntryKind::Fee, Balance{available: 88, held: 0}),
+            (EntryKind::Reversal, Balance{available: 98, held: 0}),
+            (EntryKind::Reversal, Balance{available: 100, held: 0})
+        ]);
+        assert_eq!(te.get_book_balance(1, Book::House).unwrap(), 0);
+        assert!(te.is_balanced().unwrap());
+        assert_eq!(verify(&te).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_interest() {
+        let mut te = TransactionEngine::new();
+        te.set_interest(Some(InterestPolicy{rate: "0.015".parse().unwrap(), period_rows: 2}));
+        te.process_transaction(Transaction::Deposit(1, 1, 1000));
+        te.process_transaction(Transaction::Deposit(2, 2, 100));
+        te.process_transaction(Transaction::Deposit(3, 1, 1000));
+        te.flush().unwrap();
+        let mut accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
+        accounts.sort_by_key(|account| account.client_id);
+        // 1000 * 0.015 = 15, 100 * 0.015 = 1.5 rounds to 2
+        assert_eq!(accounts[0].account.total(), 2015);
+        assert_eq!(accounts[1].account.total(), 102);
+        assert_eq!(te.get_statement(2).unwrap().last().map(|(posting, _)| posting.kind()), Some(EntryKind::Interest));
+        assert!(te.is_balanced().unwrap());
+    }
+
+    #[test]
+    fn test_interest_periods() {
+        let mut te = TransactionEngine::new();
+        te.set_interest(Some(InterestPolicy{rate: "0.1".parse().unwrap(), period_rows: 1}));
+        te.process_transaction(Transaction::Deposit(0, 1, 1000));
+        te.process_transaction(Transaction::Deposit(1, 2, 10));
+        te.process_transaction(Transaction::Deposit(2, 2, 10));
+        // client 1 is paid the three periods it missed before its withdrawal, the fourth one on flush
+        te.process_transaction(Transaction::Withdrawal(3, 1, 10));
+        te.flush().unwrap();
+        let events: Vec<Event> = te.get_events(1).map(Result::unwrap).collect();
+        assert_eq!(events, vec![Event::Deposited(0, 1000), Event::InterestPaid(100), Event::InterestPaid(110), Event::InterestPaid(121),
+                                Event::Withdrew(3, 10), Event::InterestPaid(132)]);
+        // the interest does not belong to the transaction 0
+        assert_eq!(te.get_balance_at(1, 0).unwrap(), Some(Balance{available: 1000, held: 0}));
+        assert!(te.is_balanced().unwrap());
+    }
+
+    #[test]
+    fn test_reversal() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 42));
+        te.process_transaction(Transaction::Withdrawal(2, 1, 12));
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        te.process_transaction(Transaction::Reversal(2, 1));
+        te.process_transaction(Transaction::Reversal(2, 1));
+        te.process_transaction(Transaction::Reversal(1, 2));
+        te.process_transaction(Transaction::Reversal(1, 1));
+        te.process_transaction(Transaction::Deposit(1, 1, 10));
+        let accounts: Vec<ClientAccount> = te.get_accounts().map(Result::unwrap).collect();
+        assert_eq!(accounts[0].account.total(), 0);
+        assert!(!accounts[0].account.is_locked());
+        let reasons: Vec<RejectReason> = te.get_rejections().map(Result::unwrap).map(|rejection| rejection.reason.clone()).collect();
+        assert_eq!(reasons, vec![
+            RejectReason::NotADeposit,
+            RejectReason::ReversedTransaction,
+            RejectReason::WrongClient,
+            RejectReason::DuplicateTransaction
+        ]);
+        assert_eq!(te.get_statement(1).unwrap().map(|(posting, _)| posting.kind()).collect::<Vec<EntryKind>>(),
+                   vec![EntryKind::Deposit, EntryKind::Withdrawal, EntryKind::Reversal, EntryKind::Reversal]);
+        assert!(te.is_balanced().unwrap());
+    }
+
+    #[test]
+    fn test_reversal_withdrawn_or_disputed() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 42));
+        te.process_transaction(Transaction::Deposit(2, 1, 10));
+        te.process_transaction(Transaction::Withdrawal(3, 1, 20));
+        te.process_transaction(Transaction::Reversal(1, 1));
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        te.process_transaction(Transaction::Reversal(2, 1));
+        let reasons: Vec<RejectReason> = te.get_rejections().map(Result::unwrap).map(|rejection| rejection.reason.clone()).collect();
+        assert_eq!(reasons, vec![RejectReason::InsufficientFunds, RejectReason::AlreadyDisputed]);
+    }
+
+    #[test]
+    fn test_partial_disputes() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 100));
+        te.process_transaction(Transaction::Dispute(1, 1, Some(30), None));
+        te.process_transaction(Transaction::Dispute(1, 1, Some(50), None));
+        // only 20 are left undisputed
+        te.process_transaction(Transaction::Dispute(1, 1, Some(30), None));
+        te.process_transaction(Transaction::Resolve(1, 1, Some(40)));
+        te.process_transaction(Transaction::Chargeback(1, 1, Some(50)));
+        te.process_transaction(Transaction::Chargeback(1, 1, Some(30)));
+        let account = te.get_accounts().map(Result::unwrap).next().unwrap().account;
+        assert_eq!(account.available(), 60);
+        assert_eq!(account.held(), 10);
+        assert!(account.is_locked());
+        // the resolved part can be disputed again but the charged back one cannot, 60 are left undisputed
+        te.process_transaction(Transaction::Dispute(1, 1, Some(61), None));
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        assert_eq!(te.get_accounts().map(Result::unwrap).next().unwrap().account.held(), 70);
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Resolve(1, 1, Some(0)));
+        te.process_transaction(Transaction::Resolve(1, 1, None));
+        let account = te.get_accounts().map(Result::unwrap).next().unwrap().account;
+        assert_eq!(account.available(), 70);
+        assert_eq!(account.held(), 0);
+        assert_eq!(te.get_transactions().map(Result::unwrap).count(), 0);
+        let reasons: Vec<RejectReason> = te.get_rejections().map(Result::unwrap).map(|rejection| rejection.reason.clone()).collect();
+        assert_eq!(reasons, vec![RejectReason::ExceedsTransaction, RejectReason::ExceedsDispute, RejectReason::ExceedsTransaction,
+                                 RejectReason::AlreadyDisputed, RejectReason::InvalidAmount]);
+        assert!(verify(&te).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_resolve_rule() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 100));
+        te.process_transaction(Transaction::Dispute(1, 1, Some(30), None));
+        te.process_transaction(Transaction::Resolve(1, 1, Some(10)));
+        // the partly resolved part is released and can be disputed again
+        te.process_transaction(Transaction::Dispute(1, 1, Some(80), None));
+        assert_eq!(te.get_accounts().map(Result::unwrap).next().unwrap().account.held(), 100);
+        te.process_transaction(Transaction::Resolve(1, 1, None));
+        // nothing is held anymore, the deposit is forgotten and its ID stays reserved
+        assert_eq!(te.get_transactions().count(), 0);
+        te.process_transaction(Transaction::Dispute(1, 1, None, None));
+        te.process_transaction(Transaction::Deposit(1, 1, 5));
+        let reasons: Vec<RejectReason> = te.get_rejections().map(Result::unwrap).map(|rejection| rejection.reason.clone()).collect();
+        assert_eq!(reasons, vec![RejectReason::ExpiredTransaction, RejectReason::DuplicateTransaction]);
+        let account = te.get_accounts().map(Result::unwrap).next().unwrap().account;
+        assert_eq!((account.available(), account.held()), (100, 0));
+        assert!(verify(&te).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_resolve_refused() {
+        // a disputed deposit without its account, as a damaged storage could hold
+        let mut storage = MemoryStorage::new();
+        storage.insert_transaction(1, InnerTransaction{client_id: 1, disputed: 10, is_withdrawal: false, amount: 10, stamp: 0}).unwrap();
+        let mut te = TransactionEngine::with_storage(Box::new(storage)).unwrap();
+        te.process_transaction(Transaction::Resolve(1, 1, None));
+        assert_eq!(te.get_rejections().map(Result::unwrap).map(|rejection| rejection.reason.clone()).collect::<Vec<RejectReason>>(),
+                   vec![RejectReason::UnknownAccount]);
+        // the refused resolve leaves the deposit disputed
+        assert_eq!(te.get_open_disputes().unwrap().map(|dispute| (dispute.tx, dispute.amount)).collect::<Vec<_>>(), vec![(1, 10)]);
+    }
+
+    #[test]
+    fn test_dispute_cases() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 100));
+        te.process_transaction(Transaction::Deposit(2, 1, 50));
+        te.process_transaction(Transaction::Deposit(3, 2, 10));
+        let info = DisputeInfo{reason_code: Some("4837".to_string()), case_id: Some("CB-1".to_string()), deadline: Some("2024-06-01".to_string())};
+        te.process_transaction(Transaction::Dispute(1, 1, Some(30), Some(Box::new(info.clone()))));
+        te.process_transaction(Transaction::Dispute(1, 1, Some(20), None));
+        te.process_transaction(Transaction::Chargeback(1, 1, Some(10)));
+        te.process_transaction(Transaction::Resolve(1, 1, None));
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        te.process_transaction(Transaction::Dispute(3, 2, None, None));
+        te.process_transaction(Transaction::Resolve(3, 2, None));
+        // a refused dispute opens no case
+        te.process_transaction(Transaction::Dispute(4, 2, None, None));
+        let cases: Vec<(u32, CaseStatus, Amount, Amount)> = te.get_dispute_cases().map(Result::unwrap)
+            .map(|case| (case.tx, case.status, case.disputed, case.charged_back))
+            .collect();
+        assert_eq!(cases, vec![(1, CaseStatus::ChargedBack, 50, 10), (2, CaseStatus::Open, 50, 0), (3, CaseStatus::Resolved, 10, 0)]);
+        assert_eq!(te.get_dispute_cases().next().unwrap().unwrap().info, info);
+    }
+
+    #[test]
+    fn test_open_disputes() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(2, 1, 100));
+        te.process_transaction(Transaction::Deposit(1, 2, 50));
+        te.process_transaction(Transaction::Deposit(3, 2, 10));
+        te.process_transaction(Transaction::Dispute(2, 1, Some(30), None));
+        te.process_transaction(Transaction::Dispute(1, 2, None, None));
+        te.process_transaction(Transaction::Dispute(3, 2, None, None));
+        te.process_transaction(Transaction::Resolve(3, 2, None));
+        te.process_transaction(Transaction::Dispute(2, 1, Some(20), None));
+        let disputes: Vec<OpenDispute> = te.get_open_disputes().unwrap().collect();
+        assert_eq!(disputes, vec![
+            OpenDispute{tx: 1, client_id: 2, amount: 50, opened: 5},
+            OpenDispute{tx: 2, client_id: 1, amount: 50, opened: 4}
+        ]);
+        let held: Amount = te.get_accounts().map(Result::unwrap).map(|account| account.account.held()).sum();
+        assert_eq!(held, disputes.iter().map(|dispute| dispute.amount).sum());
+    }
+
+    #[test]
+    fn test_open_disputes_line() {
+        let mut te = TransactionEngine::new();
+        te.process_line(2, Transaction::Deposit(1, 1, 100));
+        te.process_line(7, Transaction::Dispute(1, 1, Some(30), None));
+        te.process_line(9, Transaction::Dispute(1, 1, Some(20), None));
+        let disputes: Vec<OpenDispute> = te.get_open_disputes().unwrap().collect();
+        assert_eq!(disputes, vec![OpenDispute{tx: 1, client_id: 1, amount: 50, opened: 7}]);
+    }
+
+    #[test]
+    fn test_what_if() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 100));
+        te.process_transaction(Transaction::Deposit(2, 2, 50));
+        te.process_transaction(Transaction::Deposit(3, 3, 10));
+        let mut before: Vec<(ClientID, Account)> = te.get_accounts().map(Result::unwrap).map(|account| (account.client_id, account.account)).collect();
+        before.sort_by_key(|(cx, _)| *cx);
+        let changes = te.what_if(vec![
+            Transaction::Dispute(1, 1, None, None),
+            Transaction::Chargeback(1, 1, None),
+            Transaction::Withdrawal(4, 2, 20),
+            Transaction::Withdrawal(5, 2, 20),
+            Transaction::Withdrawal(6, 2, 20),
+            Transaction::Deposit(7, 4, 5)
+        ]).unwrap().unwrap();
+        assert_eq!(changes.iter().map(|change| change.client_id).collect::<Vec<_>>(), vec![1, 2, 4]);
+        assert_eq!(changes[0].before, Some(before[0].1));
+        assert_eq!((changes[0].after.total(), changes[0].after.is_locked()), (0, true));
+        assert_eq!(changes[1].after.available(), 10);
+        assert_eq!((changes[2].before, changes[2].after.available()), (None, 5));
+
+        // the engine itself is untouched
+        let mut after: Vec<(ClientID, Account)> = te.get_accounts().map(Result::unwrap).map(|account| (account.client_id, account.account)).collect();
+        after.sort_by_key(|(cx, _)| *cx);
+        assert_eq!(after, before);
+        assert_eq!(te.get_rejections().count(), 0);
+        assert!(te.get_transactions().map(Result::unwrap).any(|(tx, _)| tx == 1));
+    }
+
+    #[test]
+    fn test_dispute_withdrawals() {
+        let mut te = TransactionEngine::new();
+        te.set_dispute_withdrawals(true);
+        te.process_transaction(Transaction::Deposit(1, 1, 100));
+        te.process_transaction(Transaction::Withdrawal(2, 1, 30));
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        let account = te.get_accounts().map(Result::unwrap).next().unwrap().account;
+        assert_eq!((account.available(), account.held()), (70, 30));
+        te.process_transaction(Transaction::Resolve(2, 1, None));
+        let account = te.get_accounts().map(Result::unwrap).next().unwrap().account;
+        assert_eq!((account.available(), account.held(), account.is_locked()), (70, 0, false));
+        assert_eq!(te.get_rejections().count(), 0);
+        assert!(te.is_balanced().unwrap());
+    }
+
+    #[test]
+    fn test_chargeback_withdrawal() {
+        let mut te = TransactionEngine::new();
+        te.set_dispute_withdrawals(true);
+        te.process_transaction(Transaction::Deposit(1, 1, 100));
+        te.process_transaction(Transaction::Withdrawal(2, 1, 30));
+        te.process_transaction(Transaction::Dispute(2, 1, Some(10), None));
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        let account = te.get_accounts().map(Result::unwrap).next().unwrap().account;
+        assert_eq!((account.available(), account.held()), (70, 30));
+        // the withdrawn amount is credited back and the account locked
+        te.process_transaction(Transaction::Chargeback(2, 1, None));
+        let account = te.get_accounts().map(Result::unwrap).next().unwrap().account;
+        assert_eq!((account.available(), account.held(), account.is_locked()), (100, 0, true));
+        assert_eq!(te.get_rejections().count(), 0);
+        assert!(te.is_balanced().unwrap());
+        let kinds: Vec<EntryKind> = te.get_statement(1).unwrap().map(|(posting, _)| posting.kind()).collect();
+        assert_eq!(kinds, vec![EntryKind::Deposit, EntryKind::Withdrawal, EntryKind::Dispute, EntryKind::Dispute, EntryKind::Chargeback]);
+    }
+
+    // a memory storage whose transaction writes fail for one transaction ID
+    struct FailingStorage {
+        inner: MemoryStorage,
+        failing_tx: TransactionID
+    }
+
+    impl Storage for FailingStorage {
+        fn account(&self, cx: ClientID) -> StorageResult<Option<Account>> { self.inner.account(cx) }
+        fn upsert_account(&mut self, cx: ClientID, account: &Account) -> StorageResult<()> { self.inner.upsert_account(cx, account) }
+        fn accounts(&self) -> Box<dyn Iterator<Item=StorageResult<(ClientID, Account)>> + '_> { self.inner.accounts() }
+        fn transaction(&self, tx: TransactionID) -> StorageResult<Option<InnerTransaction>> { self.inner.transaction(tx) }
+        fn insert_transaction(&mut self, tx: TransactionID, transaction: InnerTransaction) -> StorageResult<()> {
+            if tx == self.failing_tx {
+                return Err(io::Error::other("disk full").into());
+            }
+            self.inner.insert_transaction(tx, transaction)
+        }
+        fn update_transaction(&mut self, tx: TransactionID, transaction: InnerTransaction) -> StorageResult<()> { self.inner.update_transaction(tx, transaction) }
+        fn transactions(&self) -> Box<dyn Iterator<Item=StorageResult<(TransactionID, InnerTransaction)>> + '_> { self.inner.transactions() }
+        fn expire_transaction(&mut self, tx: TransactionID) -> StorageResult<()> { self.inner.expire_transaction(tx) }
+        fn is_expired(&self, tx: TransactionID) -> StorageResult<bool> { self.inner.is_expired(tx) }
+        fn expire_before(&mut self, cutoff: u64) -> StorageResult<usize> { self.inner.expire_before(cutoff) }
+        fn reverse_transaction(&mut self, tx: TransactionID) -> StorageResult<()> { self.inner.reverse_transaction(tx) }
+        fn is_reversed(&self, tx: TransactionID) -> StorageResult<bool> { self.inner.is_reversed(tx) }
+        fn record(&mut self, cx: ClientID, event: Event) -> StorageResult<()> { self.inner.record(cx, event) }
+        fn events(&self, cx: ClientID) -> Box<dyn Iterator<Item=StorageResult<Event>> + '_> { self.inner.events(cx) }
+        fn flag(&self, cx: ClientID) -> StorageResult<Option<Flag>> { self.inner.flag(cx) }
+        fn set_flag(&mut self, cx: ClientID, flag: &Flag) -> StorageResult<()> { self.inner.set_flag(cx, flag) }
+        fn interest_period(&self, cx: ClientID) -> StorageResult<Option<u64>> { self.inner.interest_period(cx) }
+        fn set_interest_period(&mut self, cx: ClientID, period: u64) -> StorageResult<()> { self.inner.set_interest_period(cx, period) }
+        fn record_rejection(&mut self, rejection: &Rejection) -> StorageResult<()> { self.inner.record_rejection(rejection) }
+        fn rejections(&self) -> Box<dyn Iterator<Item=StorageResult<Rejection>> + '_> { self.inner.rejections() }
+        fn open_dispute_case(&self, tx: TransactionID) -> StorageResult<Option<DisputeCase>> { self.inner.open_dispute_case(tx) }
+        fn upsert_dispute_case(&mut self, case: &DisputeCase) -> StorageResult<()> { self.inner.upsert_dispute_case(case) }
+        fn dispute_cases(&self) -> Box<dyn Iterator<Item=StorageResult<DisputeCase>> + '_> { self.inner.dispute_cases() }
+        fn position(&self) -> StorageResult<u64> { self.inner.position() }
+        fn set_position(&mut self, position: u64) -> StorageResult<()> { self.inner.set_position(position) }
+    }
+
+    #[test]
+    fn test_storage_failure() {
+        let mut inner = MemoryStorage::new();
+        inner.set_position(10).unwrap();
+        let mut te = TransactionEngine::with_storage(Box::new(FailingStorage{inner, failing_tx: 2})).unwrap();
+        te.process_transaction(Transaction::Deposit(1, 1, 10));
+        assert!(te.failure().is_none());
+        te.process_transaction(Transaction::Deposit(2, 1, 20));
+        assert!(te.failure().is_some());
+        // nothing is processed after the failure, and it is not a rejection
+        te.process_transaction(Transaction::Deposit(3, 1, 30));
+        assert_eq!(te.get_rejections().count(), 0);
+        assert!(te.get_transactions().map(Result::unwrap).all(|(tx, _)| tx == 1));
+        assert_eq!(te.get_transactions().map(Result::unwrap).next().unwrap().1.stamp, 11);
+        assert!(te.try_clone().unwrap().is_none());
+    }
+}
@@ -0,0 +1,748 @@
+// Compact storage of the deposits that can still be disputed and the withdrawals that can still be reversed.
+// Transactions are indexed by transaction ID in fixed size pages allocated on first use. A page holds a short sorted list
+// while few of its IDs are used, and becomes a dense struct-of-arrays once it fills up, so sparse IDs do not pay for
+// whole pages. With the `spill` feature and a memory budget, the least recently used pages are spilled to a temporary
+// file so the whole u32 transaction ID space can be used within the budget.
+// Transactions evicted by the retention policy are only remembered as an expired bit, reversed ones as a reversed bit.
+// These bits, the oldest stamp of every page and the spill file slots are never spilled, they are counted against the
+// budget and leave less room for resident pages; past the budget on their own, only the page in use stays resident.
+// The amount held by open disputes is kept per deposit since a dispute can cover part of it.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+#[cfg(feature = "spill")]
+use std::fs::File;
+#[cfg(feature = "spill")]
+use std::io::{Read, Seek, SeekFrom, Write};
+#[cfg(feature = "spill")]
+use std::path::{Path, PathBuf};
+use crate::common_types::*;
+
+const PAGE_BITS: u32 = 12;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+const WORDS: usize = PAGE_SIZE / 64;
+#[cfg(feature = "spill")]
+const PAGE_BYTES: usize = PAGE_SIZE * (std::mem::size_of::<ClientID>() + 2 * std::mem::size_of::<Amount>() + 8) + 2 * WORDS * 8;
+// a page turns dense past that many transactions, where its sorted list would take more room than the dense slots
+// (about 2662 transactions, at 40 bytes per entry against 26 per slot)
+const SPARSE_MAX: usize = PAGE_SIZE * (std::mem::size_of::<ClientID>() + 2 * std::mem::size_of::<Amount>() + 8)
+    / std::mem::size_of::<(u16, InnerTransaction)>();
+// rough room taken by an entry of the bookkeeping hash maps, key, value and control bytes
+#[cfg(feature = "spill")]
+const MAP_ENTRY_BYTES: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InnerTransaction {
+    pub client_id: ClientID,
+    // amount held by the open disputes on the transaction
+    pub disputed: Amount,
+    pub is_withdrawal: bool,
+    pub amount: Amount,
+    // row position or timestamp of the transaction, depending on the retention policy
+    pub stamp: u64
+}
+
+impl InnerTransaction {
+    pub fn is_disputed(&self) -> bool {
+        self.disputed > 0
+    }
+}
+
+// the transactions of a dense page, one slot per transaction ID of the page
+#[derive(Clone)]
+struct Dense {
+    clients: Vec<ClientID>,
+    amounts: Vec<Amount>,
+    disputed: Vec<Amount>,
+    stamps: Vec<u64>,
+    present: Vec<u64>,
+    withdrawals: Vec<u64>
+}
+
+#[derive(Clone)]
+enum Slots {
+    // sorted by index in the page
+    Sparse(Vec<(u16, InnerTransaction)>),
+    Dense(Box<Dense>)
+}
+
+#[derive(Clone)]
+struct Page {
+    slots: Slots,
+    len: usize,
+    last_used: u64
+}
+
+fn bit(bits: &[u64], index: usize) -> bool {
+    bits[index / 64] & (1 << (index % 64)) != 0
+}
+
+fn set_bit(bits: &mut [u64], index: usize, value: bool) {
+    if value {
+        bits[index / 64] |= 1 << (index % 64);
+    } else {
+        bits[index / 64] &= !(1 << (index % 64));
+    }
+}
+
+fn split(tx: TransactionID) -> (u32, usize) {
+    (tx >> PAGE_BITS, (tx as usize) & (PAGE_SIZE - 1))
+}
+
+// sparse per-page bitsets remembering transaction IDs that are no longer stored
+fn is_marked(marks: &HashMap<u32, Vec<u64>>, tx: TransactionID) -> bool {
+    let (id, index) = split(tx);
+    marks.get(&id).map(|bits| bit(bits, index)).unwrap_or(false)
+}
+
+fn mark(marks: &mut HashMap<u32, Vec<u64>>, tx: TransactionID) {
+    let (id, index) = split(tx);
+    set_bit(marks.entry(id).or_insert_with(|| vec![0; WORDS]), index, true);
+}
+
+impl Dense {
+    fn new() -> Self {
+        Self {
+            clients: vec![0; PAGE_SIZE],
+            amounts: vec![0; PAGE_SIZE],
+            disputed: vec![0; PAGE_SIZE],
+            stamps: vec![0; PAGE_SIZE],
+            present: vec![0; WORDS],
+            withdrawals: vec![0; WORDS]
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<InnerTransaction> {
+        if bit(&self.present, index) {
+            Some(InnerTransaction {
+                client_id: self.clients[index],
+                disputed: self.disputed[index],
+                is_withdrawal: bit(&self.withdrawals, index),
+                amount: self.amounts[index],
+                stamp: self.stamps[index]
+            })
+        } else {
+            None
+        }
+    }
+
+    fn set(&mut self, index: usize, transaction: InnerTransaction) {
+        self.clients[index] = transaction.client_id;
+        self.amounts[index] = transaction.amount;
+        self.disputed[index] = transaction.disputed;
+        self.stamps[index] = transaction.stamp;
+        set_bit(&mut self.present, index, true);
+        set_bit(&mut self.withdrawals, index, transaction.is_withdrawal);
+    }
+
+    fn clear(&mut self, index: usize) {
+        set_bit(&mut self.present, index, false);
+        self.disputed[index] = 0;
+        set_bit(&mut self.withdrawals, index, false);
+    }
+}
+
+impl Page {
+    fn new() -> Self {
+        Self {
+            slots: Slots::Sparse(Vec::new()),
+            len: 0,
+            last_used: 0
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<InnerTransaction> {
+        match &self.slots {
+            Slots::Sparse(entries) => entries.binary_search_by_key(&(index as u16), |(index, _)| *index).ok().map(|position| entries[position].1),
+            Slots::Dense(dense) => dense.get(index)
+        }
+    }
+
+    // stores the transaction at `index`, returns whether it was not there yet
+    fn set(&mut self, index: usize, transaction: InnerTransaction) -> bool {
+        let inserted = match &mut self.slots {
+            Slots::Sparse(entries) => match entries.binary_search_by_key(&(index as u16), |(index, _)| *index) {
+                Ok(position) => {
+                    entries[position].1 = transaction;
+                    false
+                },
+                Err(position) => {
+                    entries.insert(position, (index as u16, transaction));
+                    true
+                }
+            },
+            Slots::Dense(dense) => {
+                let inserted = dense.get(index).is_none();
+                dense.set(index, transaction);
+                inserted
+            }
+        };
+        if inserted {
+            self.len += 1;
+            if self.len > SPARSE_MAX {
+                self.make_dense();
+            }
+        }
+        inserted
+    }
+
+    fn remove(&mut self, index: usize) -> Option<InnerTransaction> {
+        let transaction = match &mut self.slots {
+            Slots::Sparse(entries) => {
+                let position = entries.binary_search_by_key(&(index as u16), |(index, _)| *index).ok()?;
+                entries.remove(position).1
+            },
+            Slots::Dense(dense) => {
+                let transaction = dense.get(index)?;
+                dense.clear(index);
+                transaction
+            }
+        };
+        self.len -= 1;
+        Some(transaction)
+    }
+
+    fn make_dense(&mut self) {
+        if let Slots::Sparse(entries) = &self.slots {
+            let mut dense = Dense::new();
+            entries.iter().for_each(|(index, transaction)| dense.set(*index as usize, *transaction));
+            self.slots = Slots::Dense(Box::new(dense));
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item=(usize, InnerTransaction)> + '_> {
+        match &self.slots {
+            Slots::Sparse(entries) => Box::new(entries.iter().map(|(index, transaction)| (*index as usize, *transaction))),
+            Slots::Dense(dense) => Box::new((0..PAGE_SIZE).filter_map(move |index| dense.get(index).map(|transaction| (index, transaction))))
+        }
+    }
+
+    // spilled pages are always written dense, so every page takes the same room in the file
+    #[cfg(feature = "spill")]
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut page = self.clone();
+        page.make_dense();
+        let Slots::Dense(dense) = &page.slots else { unreachable!() };
+        let mut bytes = Vec::with_capacity(PAGE_BYTES);
+        dense.clients.iter().for_each(|client| bytes.extend_from_slice(&client.to_le_bytes()));
+        dense.amounts.iter().chain(dense.disputed.iter()).for_each(|amount| bytes.extend_from_slice(&amount.to_le_bytes()));
+        dense.stamps.iter().for_each(|stamp| bytes.extend_from_slice(&stamp.to_le_bytes()));
+        dense.present.iter().chain(dense.withdrawals.iter()).for_each(|word| bytes.extend_from_slice(&word.to_le_bytes()));
+        bytes
+    }
+
+    #[cfg(feature = "spill")]
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let (clients, rest) = bytes.split_at(PAGE_SIZE * std::mem::size_of::<ClientID>());
+        let (amounts, rest) = rest.split_at(PAGE_SIZE * std::mem::size_of::<Amount>());
+        let (disputed, rest) = rest.split_at(PAGE_SIZE * std::mem::size_of::<Amount>());
+        let (stamps, rest) = rest.split_at(PAGE_SIZE * 8);
+        let (present, withdrawals) = rest.split_at(WORDS * 8);
+        let to_amounts = |bytes: &[u8]| -> Vec<Amount> {
+            bytes.chunks_exact(std::mem::size_of::<Amount>()).map(|chunk| Amount::from_le_bytes(chunk.try_into().unwrap())).collect()
+        };
+        let words = |bytes: &[u8]| -> Vec<u64> {
+            bytes.chunks_exact(8).map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap())).collect()
+        };
+        let dense = Dense {
+            clients: clients.chunks_exact(2).map(|chunk| ClientID::from_le_bytes(chunk.try_into().unwrap())).collect(),
+            amounts: to_amounts(amounts),
+            disputed: to_amounts(disputed),
+            stamps: words(stamps),
+            present: words(present),
+            withdrawals: words(withdrawals)
+        };
+        let len = dense.present.iter().map(|word| word.count_ones() as usize).sum();
+        let slots = if len > SPARSE_MAX {
+            Slots::Dense(Box::new(dense))
+        } else {
+            Slots::Sparse((0..PAGE_SIZE).filter_map(|index| dense.get(index).map(|transaction| (index as u16, transaction))).collect())
+        };
+        Self {
+            slots,
+            len,
+            last_used: 0
+        }
+    }
+}
+
+#[cfg(feature = "spill")]
+struct Spill {
+    file: File,
+    dir: PathBuf,
+    memory_budget: usize,
+    // every page that was spilled at least once keeps its slot in the file
+    slots: HashMap<u32, u64>,
+    free_slots: Vec<u64>,
+    next_slot: u64
+}
+
+#[cfg(feature = "spill")]
+impl Spill {
+    // copies the spilled pages to a new temporary file in the same directory
+    fn try_clone(&self) -> io::Result<Self> {
+        let mut source = &self.file;
+        let mut file = tempfile::tempfile_in(&self.dir)?;
+        source.seek(SeekFrom::Start(0))?;
+        io::copy(&mut source, &mut file)?;
+        Ok(Self {
+            file,
+            dir: self.dir.clone(),
+            memory_budget: self.memory_budget,
+            slots: self.slots.clone(),
+            free_slots: self.free_slots.clone(),
+            next_slot: self.next_slot
+        })
+    }
+
+    fn read(&self, slot: u64) -> io::Result<Page> {
+        let mut file = &self.file;
+        let mut bytes = vec![0; PAGE_BYTES];
+        file.seek(SeekFrom::Start(slot * PAGE_BYTES as u64))?;
+        file.read_exact(&mut bytes)?;
+        Ok(Page::from_bytes(&bytes))
+    }
+
+    fn write(&mut self, id: u32, page: &Page) -> io::Result<()> {
+        let slot = match self.slots.get(&id) {
+            Some(slot) => *slot,
+            None => {
+                let slot = self.free_slots.pop().unwrap_or_else(|| {
+                    self.next_slot += 1;
+                    self.next_slot - 1
+                });
+                self.slots.insert(id, slot);
+                slot
+            }
+        };
+        self.file.seek(SeekFrom::Start(slot * PAGE_BYTES as u64))?;
+        self.file.write_all(&page.to_bytes())
+    }
+
+    // how many pages fit in the budget next to the bookkeeping of the store and the slots, at least the page in use
+    fn max_resident_pages(&self, bookkeeping: usize) -> usize {
+        let slots = self.slots.len() * MAP_ENTRY_BYTES + self.free_slots.len() * 8;
+        (self.memory_budget.saturating_sub(bookkeeping + slots) / PAGE_BYTES).max(1)
+    }
+
+    fn release(&mut self, id: u32) {
+        if let Some(slot) = self.slots.remove(&id) {
+            self.free_slots.push(slot);
+        }
+    }
+}
+
+// reading or writing the spill file can fail, every operation touching a page returns the I/O error
+pub struct TransactionStore {
+    pages: HashMap<u32, Page>,
+    #[cfg(feature = "spill")]
+    spill: Option<Spill>,
+    // oldest stamp of the undisputed transactions of every page, resident or spilled
+    oldest: HashMap<u32, u64>,
+    expired: HashMap<u32, Vec<u64>>,
+    reversed: HashMap<u32, Vec<u64>>,
+    len: usize,
+    clock: u64
+}
+
+impl Default for TransactionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransactionStore {
+    pub fn new() -> Self {
+        Self {
+            pages: HashMap::new(),
+            #[cfg(feature = "spill")]
+            spill: None,
+            oldest: HashMap::new(),
+            expired: HashMap::new(),
+            reversed: HashMap::new(),
+            len: 0,
+            clock: 0
+        }
+    }
+
+    // keeps at most `memory_budget` bytes of pages in memory, the rest goes to a temporary file in `spill_dir`
+    #[cfg(feature = "spill")]
+    pub fn with_spill<P: AsRef<Path>>(memory_budget: usize, spill_dir: P) -> io::Result<Self> {
+        Ok(Self {
+            spill: Some(Spill {
+                file: tempfile::tempfile_in(spill_dir.as_ref())?,
+                dir: spill_dir.as_ref().to_path_buf(),
+                memory_budget,
+                slots: HashMap::new(),
+                free_slots: Vec::new(),
+                next_slot: 0
+            }),
+            ..Self::new()
+        })
+    }
+
+    // an independent copy of the store, spilled pages included
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            pages: self.pages.clone(),
+            #[cfg(feature = "spill")]
+            spill: self.spill.as_ref().map(Spill::try_clone).transpose()?,
+            oldest: self.oldest.clone(),
+            expired: self.expired.clone(),
+            reversed: self.reversed.clone(),
+            len: self.len,
+            clock: self.clock
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, tx: TransactionID) -> io::Result<Option<InnerTransaction>> {
+        let (id, index) = split(tx);
+        Ok(self.page(id)?.and_then(|page| page.get(index)))
+    }
+
+    pub fn contains(&self, tx: TransactionID) -> io::Result<bool> {
+        Ok(self.get(tx)?.is_some())
+    }
+
+    pub fn insert(&mut self, tx: TransactionID, transaction: InnerTransaction) -> io::Result<()> {
+        let (id, index) = split(tx);
+        let page = self.page_mut(id, true)?.expect("a page is created when missing");
+        if page.set(index, transaction) {
+            self.len += 1;
+        }
+        if !transaction.is_disputed() {
+            let oldest = self.oldest.entry(id).or_insert(transaction.stamp);
+            *oldest = (*oldest).min(transaction.stamp);
+        }
+        Ok(())
+    }
+
+    pub fn is_expired(&self, tx: TransactionID) -> bool {
+        is_marked(&self.expired, tx)
+    }
+
+    // forgets the transaction but remembers that it existed
+    pub fn expire(&mut self, tx: TransactionID) -> io::Result<Option<InnerTransaction>> {
+        let transaction = self.remove(tx)?;
+        if transaction.is_some() {
+            mark(&mut self.expired, tx);
+        }
+        Ok(transaction)
+    }
+
+    pub fn is_reversed(&self, tx: TransactionID) -> bool {
+        is_marked(&self.reversed, tx)
+    }
+
+    // forgets the transaction but remembers that it was reversed
+    pub fn reverse(&mut self, tx: TransactionID) -> io::Result<Option<InnerTransaction>> {
+        let transaction = self.remove(tx)?;
+        if transaction.is_some() {
+            mark(&mut self.reversed, tx);
+        }
+        Ok(transaction)
+    }
+
+    // expires every undisputed transaction stamped before `cutoff`, returns how many were expired
+    pub fn expire_before(&mut self, cutoff: u64) -> io::Result<usize> {
+        let ids: Vec<u32> = self.oldest.iter().filter(|(_, oldest)| **oldest < cutoff).map(|(id, _)| *id).collect();
+        let mut count = 0;
+        for id in ids {
+            let Some(page) = self.page_mut(id, false)? else {
+                self.oldest.remove(&id);
+                continue;
+            };
+            let (expired, kept): (Vec<_>, Vec<_>) = page.iter()
+                .filter(|(_, transaction)| !transaction.is_disputed())
+                .partition(|(_, transaction)| transaction.stamp < cutoff);
+            match kept.iter().map(|(_, transaction)| transaction.stamp).min() {
+                Some(oldest) => self.oldest.insert(id, oldest),
+                None => self.oldest.remove(&id)
+            };
+            for (index, _) in expired {
+                self.expire((id << PAGE_BITS) | index as u32)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn set_disputed(&mut self, tx: TransactionID, disputed: Amount) -> io::Result<()> {
+        let (id, index) = split(tx);
+        if let Some(page) = self.page_mut(id, false)? {
+            if let Some(transaction) = page.get(index) {
+                page.set(index, InnerTransaction{disputed, ..transaction});
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, tx: TransactionID) -> io::Result<Option<InnerTransaction>> {
+        let (id, index) = split(tx);
+        let Some(page) = self.page_mut(id, false)? else {
+            return Ok(None);
+        };
+        let Some(transaction) = page.remove(index) else {
+            return Ok(None);
+        };
+        let is_empty = page.len == 0;
+        self.len -= 1;
+        if is_empty {
+            self.pages.remove(&id);
+            self.oldest.remove(&id);
+            #[cfg(feature = "spill")]
+            if let Some(spill) = self.spill.as_mut() {
+                spill.release(id);
+            }
+        }
+        Ok(Some(transaction))
+    }
+
+    // every stored transaction, a spilled page that cannot be read is yielded as its error
+    pub fn iter(&self) -> impl Iterator<Item=io::Result<(TransactionID, InnerTransaction)>> + '_ {
+        let ids: Vec<u32> = self.pages.keys().chain(self.spilled_ids()).copied().collect();
+        ids.into_iter().flat_map(move |id| {
+            let transactions: Vec<io::Result<(TransactionID, InnerTransaction)>> = match self.page(id) {
+                Ok(page) => page.iter()
+                    .flat_map(|page| page.iter().map(|(index, transaction)| Ok(((id << PAGE_BITS) | index as u32, transaction))).collect::<Vec<_>>())
+                    .collect(),
+                Err(e) => vec![Err(e)]
+            };
+            transactions
+        })
+    }
+
+    #[cfg(feature = "spill")]
+    fn spilled_ids(&self) -> impl Iterator<Item=&u32> + '_ {
+        self.spill.iter()
+            .flat_map(|spill| spill.slots.keys())
+            .filter(|id| !self.pages.contains_key(id))
+    }
+
+    #[cfg(not(feature = "spill"))]
+    fn spilled_ids(&self) -> impl Iterator<Item=&u32> + '_ {
+        std::iter::empty()
+    }
+
+    fn page(&self, id: u32) -> io::Result<Option<Cow<'_, Page>>> {
+        if let Some(page) = self.pages.get(&id) {
+            return Ok(Some(Cow::Borrowed(page)));
+        }
+        #[cfg(feature = "spill")]
+        if let Some(spill) = &self.spill {
+            if let Some(slot) = spill.slots.get(&id) {
+                return Ok(Some(Cow::Owned(spill.read(*slot)?)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn page_mut(&mut self, id: u32, create: bool) -> io::Result<Option<&mut Page>> {
+        self.clock += 1;
+        if !self.pages.contains_key(&id) {
+            let page = match self.page(id)? {
+                Some(page) => page.into_owned(),
+                None if create => Page::new(),
+                None => return Ok(None)
+            };
+            self.pages.insert(id, page);
+            self.evict(id)?;
+        }
+        let page = self.pages.get_mut(&id).expect("the page was just made resident");
+        page.last_used = self.clock;
+        Ok(Some(page))
+    }
+
+    // room taken by the expired and reversed bits and the oldest stamps, kept in memory whatever the budget
+    #[cfg(feature = "spill")]
+    fn bookkeeping_bytes(&self) -> usize {
+        (self.expired.len() + self.reversed.len()) * (WORDS * 8 + MAP_ENTRY_BYTES) + self.oldest.len() * MAP_ENTRY_BYTES
+    }
+
+    // spills the least recently used pages until the memory budget is respected again
+    #[cfg(feature = "spill")]
+    fn evict(&mut self, keep: u32) -> io::Result<()> {
+        let bookkeeping = self.bookkeeping_bytes();
+        if let Some(spill) = self.spill.as_mut() {
+            while self.pages.len() > spill.max_resident_pages(bookkeeping) {
+                let Some(id) = self.pages.iter()
+                    .filter(|(id, _)| **id != keep)
+                    .min_by_key(|(_, page)| page.last_used)
+                    .map(|(id, _)| *id) else {
+                    break;
+                };
+                // the page stays resident if it cannot be written
+                spill.write(id, &self.pages[&id])?;
+                self.pages.remove(&id);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "spill"))]
+    fn evict(&mut self, _keep: u32) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common_types::Amount;
+    use crate::transaction_store::{InnerTransaction, Slots, TransactionStore, PAGE_SIZE, SPARSE_MAX};
+    #[cfg(feature = "spill")]
+    use crate::transaction_store::PAGE_BYTES;
+
+    fn transaction(client_id: u16, amount: u64) -> InnerTransaction {
+        InnerTransaction{client_id, disputed: 0, is_withdrawal: false, amount: amount as Amount, stamp: amount}
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut store = TransactionStore::new();
+        store.insert(42, transaction(1, 12)).unwrap();
+        assert_eq!(store.get(42).unwrap(), Some(transaction(1, 12)));
+        assert_eq!(store.get(43).unwrap(), None);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.remove(42).unwrap(), Some(transaction(1, 12)));
+        assert_eq!(store.remove(42).unwrap(), None);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn max_transaction_id() {
+        let mut store = TransactionStore::new();
+        store.insert(u32::MAX, transaction(1, 12)).unwrap();
+        store.insert(0, transaction(2, 30)).unwrap();
+        assert_eq!(store.get(u32::MAX).unwrap(), Some(transaction(1, 12)));
+        assert_eq!(store.get(0).unwrap(), Some(transaction(2, 30)));
+    }
+
+    #[test]
+    fn set_disputed() {
+        let mut store = TransactionStore::new();
+        store.insert(1, transaction(1, 12)).unwrap();
+        store.set_disputed(1, 5).unwrap();
+        store.set_disputed(2, 5).unwrap();
+        assert_eq!(store.get(1).unwrap().unwrap().disputed, 5);
+        assert!(!store.contains(2).unwrap());
+    }
+
+    #[test]
+    fn dense_page() {
+        let mut store = TransactionStore::new();
+        for tx in 0..SPARSE_MAX as u32 {
+            store.insert(tx + 2, transaction(1, tx as u64)).unwrap();
+        }
+        assert!(matches!(store.pages[&0].slots, Slots::Sparse(_)));
+        store.insert(1, transaction(2, 1)).unwrap();
+        assert!(matches!(store.pages[&0].slots, Slots::Dense(_)));
+        assert_eq!(store.get(1).unwrap(), Some(transaction(2, 1)));
+        assert_eq!(store.get(3).unwrap(), Some(transaction(1, 1)));
+        assert_eq!(store.get(SPARSE_MAX as u32 + 2).unwrap(), None);
+        assert_eq!(store.remove(3).unwrap(), Some(transaction(1, 1)));
+        assert_eq!(store.iter().count(), SPARSE_MAX);
+    }
+
+    #[cfg(feature = "spill")]
+    #[test]
+    fn spill_to_disk() {
+        let mut store = TransactionStore::with_spill(0, std::env::temp_dir()).unwrap();
+        for page in 0..4 {
+            store.insert(page * PAGE_SIZE as u32, InnerTransaction{is_withdrawal: page == 1, ..transaction(page as u16, page as u64)}).unwrap();
+        }
+        assert_eq!(store.pages.len(), 1);
+        store.set_disputed(0, 1).unwrap();
+        for page in 0..4 {
+            let expected = InnerTransaction{client_id: page as u16, disputed: (page == 0) as Amount, is_withdrawal: page == 1, amount: page as Amount, stamp: page as u64};
+            assert_eq!(store.get(page * PAGE_SIZE as u32).unwrap(), Some(expected));
+        }
+        let mut transactions: Vec<u32> = store.iter().map(|item| item.unwrap().0).collect();
+        transactions.sort();
+        assert_eq!(transactions, vec![0, PAGE_SIZE as u32, 2 * PAGE_SIZE as u32, 3 * PAGE_SIZE as u32]);
+        assert_eq!(store.remove(PAGE_SIZE as u32).unwrap(), Some(InnerTransaction{is_withdrawal: true, ..transaction(1, 1)}));
+        assert_eq!(store.iter().count(), 3);
+
+        // the copy has its own spill file
+        let mut copy = store.try_clone().unwrap();
+        copy.remove(0).unwrap();
+        assert!(store.contains(0).unwrap());
+        assert!(!copy.contains(0).unwrap());
+        assert_eq!(copy.get(3 * PAGE_SIZE as u32).unwrap(), store.get(3 * PAGE_SIZE as u32).unwrap());
+
+        // a spilled page is read back sparse
+        assert!(matches!(store.page(2).unwrap().unwrap().slots, Slots::Sparse(_)));
+    }
+
+    #[cfg(feature = "spill")]
+    #[test]
+    fn spill_counts_bookkeeping() {
+        let mut store = TransactionStore::with_spill(2 * PAGE_BYTES + 1024, std::env::temp_dir()).unwrap();
+        store.insert(0, transaction(1, 1)).unwrap();
+        store.insert(PAGE_SIZE as u32, transaction(1, 1)).unwrap();
+        assert_eq!(store.pages.len(), 2);
+        // the expired bits of 400 pages take more than a page
+        for page in 2..402 {
+            store.insert(page * PAGE_SIZE as u32, transaction(1, 1)).unwrap();
+            store.expire(page * PAGE_SIZE as u32).unwrap();
+        }
+        store.get(0).unwrap();
+        store.insert(1, transaction(1, 1)).unwrap();
+        store.insert(PAGE_SIZE as u32 + 1, transaction(1, 1)).unwrap();
+        assert_eq!(store.pages.len(), 1);
+        assert_eq!(store.len(), 4);
+    }
+
+    #[test]
+    fn expire() {
+        let mut store = TransactionStore::new();
+        store.insert(1, transaction(1, 12)).unwrap();
+        assert!(!store.is_expired(1));
+        assert_eq!(store.expire(1).unwrap(), Some(transaction(1, 12)));
+        assert!(store.is_expired(1));
+        assert!(!store.contains(1).unwrap());
+        assert_eq!(store.expire(2).unwrap(), None);
+        assert!(!store.is_expired(2));
+    }
+
+    #[test]
+    fn expire_before() {
+        #[cfg(feature = "spill")]
+        let mut store = TransactionStore::with_spill(0, std::env::temp_dir()).unwrap();
+        #[cfg(not(feature = "spill"))]
+        let mut store = TransactionStore::new();
+        store.insert(1, transaction(1, 10)).unwrap();
+        store.insert(2, transaction(1, 20)).unwrap();
+        store.insert(3, transaction(1, 5)).unwrap();
+        store.set_disputed(3, 5).unwrap();
+        store.insert(PAGE_SIZE as u32, transaction(2, 1)).unwrap();
+        assert_eq!(store.expire_before(15).unwrap(), 2);
+        assert!(store.is_expired(1));
+        assert!(store.is_expired(PAGE_SIZE as u32));
+        assert!(store.contains(2).unwrap());
+        assert!(store.contains(3).unwrap());
+        assert_eq!(store.expire_before(15).unwrap(), 0);
+        assert_eq!(store.expire_before(25).unwrap(), 1);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn reverse() {
+        let mut store = TransactionStore::new();
+        store.insert(1, transaction(1, 12)).unwrap();
+        assert_eq!(store.reverse(1).unwrap(), Some(transaction(1, 12)));
+        assert!(store.is_reversed(1));
+        assert!(!store.is_expired(1));
+        assert!(!store.contains(1).unwrap());
+        assert_eq!(store.reverse(2).unwrap(), None);
+        assert!(!store.is_reversed(2));
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::common_types::*;
+use crate::account::Account;
+use crate::ledger::{self, Balance, Book, Posting};
+use crate::storage::StorageResult;
+use crate::transaction_engine::TransactionEngine;
+
+// the books outside the client, what they paid in and out is what the client holds
+const OUTSIDE_BOOKS: [Book; 4] = [Book::Settlement, Book::ChargebackLoss, Book::House, Book::Reversal];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Discrepancy {
+    LedgerNotBalanced(SignedAmount),
+    // the stored balance and the total rebuilt from the books
+    TotalMismatch(ClientID, Balance, Amount),
+    // the stored held amount and what the disputes of the stored transactions hold
+    HeldMismatch(ClientID, Amount, Amount),
+    LockedWithoutChargeback(ClientID)
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Discrepancy::LedgerNotBalanced(net) =>
+                write!(f, "ledger does not net to zero, net={}", net),
+            Discrepancy::TotalMismatch(cx, balance, total) =>
+                write!(f, "available and held do not add up to the total cx={} available={} held={} total={}", cx, balance.available, balance.held, total),
+            Discrepancy::HeldMismatch(cx, held, disputed) =>
+                write!(f, "held amount does not match the disputed transactions cx={} held={} disputed={}", cx, held, disputed),
+            Discrepancy::LockedWithoutChargeback(cx) =>
+                write!(f, "locked account without a chargeback or lock event cx={}", cx)
+        }
+    }
+}
+
+// rebuilds every client from the stored transactions and the ledger rather than from a replay of its account: the held
+// amount is what the disputes of its stored transactions hold and the total what the books outside the client paid in
+// and out. The stored available and held amounts must add up to that total, the stored held amount must match the
+// disputes, and a locked account must have a chargeback or lock event
+pub fn verify(engine: &TransactionEngine) -> StorageResult<Vec<Discrepancy>> {
+    let mut discrepancies = Vec::new();
+    let net = engine.get_net_balance()?;
+    if net != 0 {
+        discrepancies.push(Discrepancy::LedgerNotBalanced(net));
+    }
+
+    let mut disputed: HashMap<ClientID, Amount> = HashMap::new();
+    for item in engine.get_transactions() {
+        let (_, transaction) = item?;
+        if transaction.is_disputed() {
+            let held = disputed.entry(transaction.client_id).or_default();
+            *held = held.saturating_add(transaction.disputed);
+        }
+    }
+
+    for account in engine.get_accounts() {
+        let account = account?;
+        let disputed = disputed.get(&account.client_id).copied().unwrap_or(0);
+        let postings = engine.get_postings(account.client_id).collect::<StorageResult<Vec<Posting>>>()?;
+        let mut is_locked = false;
+        for event in engine.get_events(account.client_id) {
+            is_locked |= event?.locks();
+        }
+        discrepancies.extend(check_account(account.client_id, &account.account, &postings, disputed, is_locked));
+    }
+    Ok(discrepancies)
+}
+
+// `is_locked` tells whether the client has a chargeback or lock event
+fn check_account(cx: ClientID, account: &Account, postings: &[Posting], disputed: Amount, is_locked: bool) -> Vec<Discrepancy> {
+    let mut discrepancies = Vec::new();
+    // the books wrap around like the ledger ones, the total cast back to an amount is exact
+    let outside = OUTSIDE_BOOKS.iter().fold(0, |outside: SignedAmount, &book| outside.wrapping_add(ledger::book_balance(postings, book)));
+    let total = outside.wrapping_neg() as Amount;
+    if account.available().checked_add(account.held()) != Some(total) {
+        discrepancies.push(Discrepancy::TotalMismatch(cx, Balance{available: account.available(), held: account.held()}, total));
+    }
+    if account.held() != disputed {
+        discrepancies.push(Discrepancy::HeldMismatch(cx, account.held(), disputed));
+    }
+    if account.is_locked() && !is_locked {
+        discrepancies.push(Discrepancy::LockedWithoutChargeback(cx));
+    }
+    discrepancies
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::Account;
+    use crate::common_types::{Amount, Transaction};
+    use crate::events::Event;
+    use crate::ledger::{Balance, Posting};
+    use crate::transaction_engine::TransactionEngine;
+    use crate::verify::{check_account, verify, Discrepancy};
+
+    fn postings(events: &[Event]) -> Vec<Posting> {
+        events.iter().filter_map(Event::posting).collect()
+    }
+
+    #[test]
+    fn verify_consistent_engine() {
+        let mut te = TransactionEngine::new();
+        te.process_transaction(Transaction::Deposit(1, 1, 12));
+        te.process_transaction(Transaction::Deposit(2, 1, 30));
+        te.process_transaction(Transaction::Deposit(3, 2, 30));
+        te.process_transaction(Transaction::Withdrawal(4, 2, 10));
+        te.process_transaction(Transaction::Dispute(2, 1, None, None));
+        te.process_transaction(Transaction::Dispute(3, 2, Some(20), None));
+        te.process_transaction(Transaction::Chargeback(3, 2, None));
+        assert_eq!(verify(&te).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn check_account_matching() {
+        let mut events = Vec::new();
+        let mut account = Account::new();
+        events.push(account.deposit(1, 42).unwrap());
+        events.push(account.dispute(1, 42).unwrap());
+        events.push(account.lock(1));
+        assert_eq!(check_account(1, &account, &postings(&events), 42, true), vec![]);
+    }
+
+    #[test]
+    fn check_account_total_mismatch() {
+        let mut account = Account::new();
+        let events = vec![account.deposit(1, 42).unwrap()];
+        account.deposit(2, 8).unwrap();
+        assert_eq!(check_account(1, &account, &postings(&events), 0, false),
+                   vec![Discrepancy::TotalMismatch(1, Balance{available: 50, held: 0}, 42)]);
+        // a fee paid to the house leaves the total
+        let events = vec![events[0], Event::Deposited(2, 10), Event::FeeCharged(2, 2)];
+        assert_eq!(check_account(1, &account, &postings(&events), 0, false), vec![]);
+    }
+
+    #[test]
+    fn check_account_held_mismatch() {
+        let mut events = Vec::new();
+        let mut account = Account::new();
+        events.push(account.deposit(1, 42).unwrap());
+        events.push(account.dispute(1, 12).unwrap());
+        assert_eq!(check_account(1, &account, &postings(&events), 10, false), vec![Discrepancy::HeldMismatch(1, 12, 10)]);
+        assert_eq!(check_account(1, &account, &postings(&events), 14, false), vec![Discrepancy::HeldMismatch(1, 12, 14)]);
+    }
+
+    #[test]
+    fn check_account_past_signed_amounts() {
+        let mut events = Vec::new();
+        let mut account = Account::new();
+        events.push(account.deposit(1, Amount::MAX - 1).unwrap());
+        events.push(account.dispute(1, 1).unwrap());
+        assert_eq!(check_account(1, &account, &postings(&events), 1, false), vec![]);
+    }
+
+    #[test]
+    fn check_account_locked_without_chargeback() {
+        let mut account = Account::new();
+        let events = vec![account.deposit(1, 42).unwrap(), account.dispute(1, 12).unwrap()];
+        account.lock(1);
+        assert_eq!(check_account(1, &account, &postings(&events), 12, false), vec![Discrepancy::LockedWithoutChargeback(1)]);
+        assert_eq!(check_account(1, &account, &postings(&events), 12, true), vec![]);
+    }
+}
@@ -0,0 +1,90 @@
+// property-based tests: random transaction sequences must keep the engine invariants
+
+use std::collections::HashMap;
+use proptest::prelude::*;
+use transaction_engine::common_types::*;
+use transaction_engine::ledger::EntryKind;
+use transaction_engine::transaction_engine::TransactionEngine;
+use transaction_engine::verify::verify;
+
+// few clients and transaction IDs so that disputes regularly hit existing deposits
+fn transaction() -> impl Strategy<Value=Transaction> {
+    let tx = 0..32u32;
+    let cx = 0..4u16;
+    let amount = 0..1_000_000_000 as Amount;
+    let partial = prop::option::of(0..500_000_000 as Amount);
+    prop_oneof![
+        3 => (tx.clone(), cx.clone(), amount.clone()).prop_map(|(tx, cx, amount)| Transaction::Deposit(tx, cx, amount)),
+        2 => (tx.clone(), cx.clone(), amount.clone()).prop_map(|(tx, cx, amount)| Transaction::Withdrawal(tx, cx, amount)),
+        // disputes, resolves and chargebacks are partial half of the time
+        2 => (tx.clone(), cx.clone(), partial.clone()).prop_map(|(tx, cx, amount)| Transaction::Dispute(tx, cx, amount, None)),
+        1 => (tx.clone(), cx.clone(), partial.clone()).prop_map(|(tx, cx, amount)| Transaction::Resolve(tx, cx, amount)),
+        1 => (tx.clone(), cx.clone(), partial).prop_map(|(tx, cx, amount)| Transaction::Chargeback(tx, cx, amount)),
+        1 => (tx, cx).prop_map(|(tx, cx)| Transaction::Reversal(tx, cx)),
+    ]
+}
+
+fn run(transactions: &[Transaction]) -> TransactionEngine {
+    let mut te = TransactionEngine::new();
+    for transaction in transactions {
+        te.process_transaction(transaction.clone());
+    }
+    te
+}
+
+proptest! {
+    #[test]
+    fn engine_stays_consistent(transactions in prop::collection::vec(transaction(), 0..200)) {
+        let te = run(&transactions);
+        prop_assert!(te.is_balanced().unwrap());
+        prop_assert_eq!(verify(&te).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn balances_never_underflow(transactions in prop::collection::vec(transaction(), 0..200)) {
+        let te = run(&transactions);
+        let mut deposited: HashMap<ClientID, Amount> = HashMap::new();
+        for transaction in &transactions {
+            if let Transaction::Deposit(_, cx, amount) = transaction {
+                *deposited.entry(*cx).or_default() += amount;
+            }
+        }
+        for account in te.get_accounts().map(Result::unwrap) {
+            // a wrapped subtraction would show up as a balance larger than everything ever deposited
+            let deposited = deposited[&account.client_id];
+            prop_assert!(account.account.total() <= deposited);
+            prop_assert!(account.account.available() <= deposited);
+            prop_assert!(account.account.held() <= deposited);
+            prop_assert_eq!(account.account.available() + account.account.held(), account.account.total());
+        }
+    }
+
+    #[test]
+    fn held_matches_open_disputes(transactions in prop::collection::vec(transaction(), 0..200)) {
+        let te = run(&transactions);
+        let mut disputed: HashMap<ClientID, Amount> = HashMap::new();
+        for (_, transaction) in te.get_transactions().map(Result::unwrap).filter(|(_, transaction)| transaction.is_disputed()) {
+            *disputed.entry(transaction.client_id).or_default() += transaction.disputed;
+        }
+        for account in te.get_accounts().map(Result::unwrap) {
+            prop_assert_eq!(account.account.held(), disputed.get(&account.client_id).copied().unwrap_or(0));
+        }
+    }
+
+    #[test]
+    fn locked_only_after_chargeback(transactions in prop::collection::vec(transaction(), 0..200)) {
+        let te = run(&transactions);
+        for account in te.get_accounts().map(Result::unwrap) {
+            let charged_back = te.get_statement(account.client_id).unwrap().any(|(posting, _)| posting.kind() == EntryKind::Chargeback);
+            prop_assert_eq!(account.account.is_locked(), charged_back);
+        }
+    }
+
+    #[test]
+    fn accounts_replay_from_events(transactions in prop::collection::vec(transaction(), 0..200)) {
+        let te = run(&transactions);
+        for account in te.get_accounts().map(Result::unwrap) {
+            prop_assert_eq!(te.replay(account.client_id).unwrap(), account.account);
+        }
+    }
+}